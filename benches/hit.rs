@@ -0,0 +1,22 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use ruyt::*;
+
+// a small render of the random scene, which is mostly ray-object hits over its ~480 spheres; the scene and the
+// sample patterns are seeded so every run traces the same rays
+fn random_scene(c: &mut Criterion) {
+    let scene = create_random_scene(42);
+    let camera = Camera::new(V3(13.0, 2.0, 3.0), V3(0.0, 0.0, 0.0), V3(0.0, 1.0, 0.0), 20.0, 64.0 / 40.0, 0.1, 10.0);
+    let settings = RenderSettings { width: 64, height: 40, samples_per_pixel: 4, seed: 42, ..RenderSettings::default() };
+    let output = std::env::temp_dir().join("ruyt-bench-hit.ppm");
+    let output = output.to_str().unwrap();
+
+    c.bench_function("random_scene_64x40", |b| b.iter(|| render(&scene, &camera, &settings, output)));
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = random_scene
+}
+criterion_main!(benches);
@@ -0,0 +1,308 @@
+use std::sync::Arc;
+use crate::vector::*;
+use crate::image::*;
+use crate::pdf::*;
+use crate::spectrum::*;
+use crate::sampler::*;
+
+pub trait Environment {
+    fn value(&self, direction: &V3U) -> V3;
+}
+
+// faces are ordered +x, -x, +y, -y, +z, -z following the usual skybox layout
+pub struct CubeMap {
+    faces: Vec<Image>,
+}
+
+impl CubeMap {
+    pub fn new(faces: [Image; 6]) -> CubeMap {
+        CubeMap {
+            faces: faces.to_vec(),
+        }
+    }
+}
+
+impl Environment for CubeMap {
+    fn value(&self, direction: &V3U) -> V3 {
+        let (x, y, z) = (direction.x(), direction.y(), direction.z());
+        let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+
+        let (face, sc, tc, ma) =
+            if ax >= ay && ax >= az {
+                if x > 0.0 { (0, -z, -y, ax) } else { (1, z, -y, ax) }
+            } else if ay >= az {
+                if y > 0.0 { (2, x, z, ay) } else { (3, x, -z, ay) }
+            } else {
+                if z > 0.0 { (4, x, -y, az) } else { (5, -x, -y, az) }
+            };
+
+        self.faces[face].sample(0.5 * (sc / ma + 1.0), 0.5 * (tc / ma + 1.0))
+    }
+}
+
+// piecewise constant distribution over the texels of a lat-long map, proportional to luminance * sin(theta)
+struct EnvironmentDistribution {
+    width: usize,
+    height: usize,
+    // cdf over rows, then one cdf over the columns of every row
+    marginal: Vec<f32>,
+    conditional: Vec<f32>,
+    // probability of picking each texel
+    texel_prob: Vec<f32>,
+}
+
+impl EnvironmentDistribution {
+    fn new(image: &Image) -> EnvironmentDistribution {
+        let (width, height) = (image.width(), image.height());
+        let mut weights = vec![0.0; width * height];
+        for j in 0..height {
+            let sin_theta = (std::f32::consts::PI * (j as f32 + 0.5) / height as f32).sin();
+            for i in 0..width {
+                let c = image.pixel(i, j);
+                weights[j * width + i] = (0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()).max(0.0) * sin_theta;
+            }
+        }
+
+        let total: f32 = weights.iter().sum();
+        let texel_prob = if total > 0.0 {
+            weights.iter().map(|w| w / total).collect::<Vec<f32>>()
+        } else {
+            vec![1.0 / (width * height) as f32; width * height]
+        };
+
+        let mut marginal = Vec::with_capacity(height);
+        let mut conditional = Vec::with_capacity(width * height);
+        let mut acc_rows = 0.0;
+        for row in texel_prob.chunks(width) {
+            let row_sum: f32 = row.iter().sum();
+            acc_rows += row_sum;
+            marginal.push(acc_rows);
+
+            let mut acc = 0.0;
+            for p in row {
+                acc += if row_sum > 0.0 { p / row_sum } else { 1.0 / width as f32 };
+                conditional.push(acc);
+            }
+        }
+
+        EnvironmentDistribution {
+            width: width,
+            height: height,
+            marginal: marginal,
+            conditional: conditional,
+            texel_prob: texel_prob,
+        }
+    }
+
+    fn search(cdf: &[f32], r: f32) -> usize {
+        let last = *cdf.last().unwrap();
+        cdf.iter().position(|&c| r * last < c).unwrap_or(cdf.len() - 1)
+    }
+}
+
+// u follows phi around the y axis, v runs from +y (top row) to -y
+fn direction_to_uv(direction: &V3U) -> (f32, f32) {
+    let phi = direction.z().atan2(direction.x()) + std::f32::consts::PI;
+    let theta = direction.y().max(-1.0).min(1.0).acos();
+    (phi / (2.0 * std::f32::consts::PI), theta / std::f32::consts::PI)
+}
+
+fn uv_to_direction(u: f32, v: f32) -> V3 {
+    let phi = 2.0 * std::f32::consts::PI * u - std::f32::consts::PI;
+    let theta = std::f32::consts::PI * v;
+    V3(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())
+}
+
+// an equirectangular (lat-long) environment that can also be importance sampled by luminance
+pub struct EnvironmentMap {
+    image: Image,
+    distribution: Arc<EnvironmentDistribution>,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: Image) -> EnvironmentMap {
+        EnvironmentMap {
+            distribution: Arc::new(EnvironmentDistribution::new(&image)),
+            image: image,
+        }
+    }
+
+    pub fn pdf(&self) -> EnvironmentPdf {
+        EnvironmentPdf {
+            distribution: self.distribution.clone(),
+        }
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn value(&self, direction: &V3U) -> V3 {
+        let (u, v) = direction_to_uv(direction);
+        self.image.sample(u, v)
+    }
+}
+
+#[derive(Clone)]
+pub struct EnvironmentPdf {
+    distribution: Arc<EnvironmentDistribution>,
+}
+
+impl Pdf for EnvironmentPdf {
+    fn value(&self, direction: &V3U) -> f32 {
+        let d = &self.distribution;
+        let (u, v) = direction_to_uv(direction);
+        let i = ((u * d.width as f32) as usize).min(d.width - 1);
+        let j = ((v * d.height as f32) as usize).min(d.height - 1);
+        let sin_theta = (std::f32::consts::PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        // texel probability spread over its solid angle (2pi / width) * (pi / height) * sin(theta)
+        d.texel_prob[j * d.width + i] * (d.width * d.height) as f32 / (2.0 * std::f32::consts::PI * std::f32::consts::PI * sin_theta)
+    }
+
+    fn generate(&self) -> V3 {
+        self.generate_from((random(), random()))
+    }
+
+    fn generate_from(&self, r: (f32, f32)) -> V3 {
+        let d = &self.distribution;
+        let j = EnvironmentDistribution::search(&d.marginal, r.1);
+        let i = EnvironmentDistribution::search(&d.conditional[j * d.width..(j + 1) * d.width], r.0);
+
+        let u = (i as f32 + random()) / d.width as f32;
+        let v = (j as f32 + random()) / d.height as f32;
+        uv_to_direction(u, v)
+    }
+
+    fn generate_aimed(&self, r: (f32, f32)) -> (V3, bool) {
+        (self.generate_from(r), true)
+    }
+}
+
+// maps the model's luminance in kcd/m^2 to radiance of the same order as the scenes' unit-albedo lights
+const PREETHAM_SCALE: f32 = 0.05;
+
+// Preetham, Shirley and Smits (1999) analytic daylight: Perez distributions for luminance and chromaticity,
+// anchored at zenith values that depend on the sun elevation and the turbidity (2 is clear, 10 is hazy)
+pub struct PreethamSky {
+    sun: V3,
+    zenith: V3,
+    // Perez coefficients A..E for Y, x and y
+    perez: [[f32; 5]; 3],
+}
+
+impl PreethamSky {
+    pub fn new(sun_dir: V3, turbidity: f32) -> PreethamSky {
+        let t = turbidity;
+        let sun = sun_dir.normalize();
+        let theta_s = sun.y().max(0.0).min(1.0).acos();
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (std::f32::consts::PI - 2.0 * theta_s);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let chromaticity = |m: [[f32; 4]; 3]| {
+            let angles = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+            let row = |r: [f32; 4]| r.iter().zip(angles.iter()).map(|(a, b)| a * b).sum::<f32>();
+            t * t * row(m[0]) + t * row(m[1]) + row(m[2])
+        };
+        let x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        PreethamSky {
+            sun: sun,
+            zenith: V3(luminance, x, y),
+            perez: [
+                [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+                [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+                [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+            ],
+        }
+    }
+
+    fn perez(c: &[f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+        (1.0 + c[0] * (c[1] / cos_theta).exp()) * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos() * gamma.cos())
+    }
+}
+
+impl Environment for PreethamSky {
+    fn value(&self, direction: &V3U) -> V3 {
+        // below the horizon the model diverges, so the horizon color is continued downwards
+        let cos_theta = direction.y().max(0.01);
+        let gamma = direction.dot(self.sun).max(-1.0).min(1.0).acos();
+        let theta_s = self.sun.y().max(0.0).min(1.0).acos();
+
+        let channel = |i: usize, zenith: f32| {
+            zenith * PreethamSky::perez(&self.perez[i], cos_theta, gamma) / PreethamSky::perez(&self.perez[i], 1.0, theta_s)
+        };
+        let (luminance, x, y) = (channel(0, self.zenith.x()), channel(1, self.zenith.y()), channel(2, self.zenith.z()));
+        if y <= 0.0 {
+            return V3(0.0, 0.0, 0.0);
+        }
+
+        let xyz = V3(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+        xyz_to_rgb(xyz.scale(PREETHAM_SCALE)).map(&|c| c.max(0.0))
+    }
+}
+
+pub enum Backgrounds {
+    Black,
+    Uniform(V3),
+    CubeMap(CubeMap),
+    Environment(EnvironmentMap),
+    Preetham(PreethamSky),
+    // white at the horizon blending to light blue overhead
+    Sky,
+    // debug: maps each direction component from [-1,1] to a color channel in [0,1]
+    Direction,
+}
+
+impl Backgrounds {
+    pub fn uniform(color: V3) -> Backgrounds {
+        Backgrounds::Uniform(color)
+    }
+
+    pub fn cubemap(faces: [Image; 6]) -> Backgrounds {
+        Backgrounds::CubeMap(CubeMap::new(faces))
+    }
+
+    pub fn environment(image: Image) -> Backgrounds {
+        Backgrounds::Environment(EnvironmentMap::new(image))
+    }
+
+    pub fn preetham(sun_dir: V3, turbidity: f32) -> Backgrounds {
+        Backgrounds::Preetham(PreethamSky::new(sun_dir, turbidity))
+    }
+
+    // only backgrounds with a meaningful distribution are worth sampling directly
+    pub fn pdf(&self) -> Option<EnvironmentPdf> {
+        match self {
+            Backgrounds::Environment(b) => Some(b.pdf()),
+            _ => None,
+        }
+    }
+}
+
+impl Environment for Backgrounds {
+    fn value(&self, direction: &V3U) -> V3 {
+        match self {
+            Backgrounds::Black => V3(0.0, 0.0, 0.0),
+            Backgrounds::Uniform(c) => *c,
+            Backgrounds::CubeMap(b) => b.value(direction),
+            Backgrounds::Environment(b) => b.value(direction),
+            Backgrounds::Preetham(b) => b.value(direction),
+            Backgrounds::Sky => {
+                let t = 0.5 * (direction.y() + 1.0);
+                V3(1.0, 1.0, 1.0).scale(1.0 - t) + V3(0.5, 0.7, 1.0).scale(t)
+            },
+            Backgrounds::Direction => (direction.as_V3() + V3(1.0, 1.0, 1.0)).scale(0.5),
+        }
+    }
+}
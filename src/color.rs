@@ -0,0 +1,60 @@
+use crate::vector::*;
+use crate::transform::*;
+
+// the renderer works in linear sRGB (Rec.709 primaries, D65); other spaces are converted on the way in and out
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    LinearSrgb,
+    // ACES AP1 primaries with the D60 white point, the usual working space of VFX pipelines
+    AcesCg,
+}
+
+// Bradford-adapted conversions between the Rec.709 and AP1 primaries
+fn srgb_to_acescg() -> Matrix3 {
+    Matrix3::new(
+        V3(0.613097, 0.339523, 0.047379),
+        V3(0.070194, 0.916354, 0.013452),
+        V3(0.020616, 0.109570, 0.869815),
+    )
+}
+
+fn acescg_to_srgb() -> Matrix3 {
+    Matrix3::new(
+        V3(1.704859, -0.621715, -0.083299),
+        V3(-0.130078, 1.140734, -0.010560),
+        V3(-0.023964, -0.128975, 1.153013),
+    )
+}
+
+impl ColorSpace {
+    pub fn to_linear_srgb(&self, color: V3) -> V3 {
+        match self {
+            ColorSpace::LinearSrgb => color,
+            ColorSpace::AcesCg => acescg_to_srgb().apply(color),
+        }
+    }
+
+    pub fn from_linear_srgb(&self, color: V3) -> V3 {
+        match self {
+            ColorSpace::LinearSrgb => color,
+            ColorSpace::AcesCg => srgb_to_acescg().apply(color),
+        }
+    }
+}
+
+// Stephen Hill's fit of the ACES reference rendering and sRGB output transforms; takes and returns linear sRGB
+pub fn aces_output_transform(color: V3) -> V3 {
+    let input = Matrix3::new(
+        V3(0.59719, 0.35458, 0.04823),
+        V3(0.07600, 0.90834, 0.01566),
+        V3(0.02840, 0.13383, 0.83777),
+    );
+    let output = Matrix3::new(
+        V3(1.60475, -0.53108, -0.07367),
+        V3(-0.10208, 1.10813, -0.00605),
+        V3(-0.00327, -0.07276, 1.07602),
+    );
+    let rrt_and_odt = |v: f32| (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.4329510) + 0.238081);
+
+    output.apply(input.apply(color).map(&rrt_and_odt)).map(&|x| x.max(0.0).min(1.0))
+}
@@ -1,5 +1,11 @@
+use std::sync::Arc;
+use std::collections::HashMap;
+use rand::{Rng, SeedableRng};
+use rand::prng::XorShiftRng;
 use crate::vector::*;
 use crate::materials::*;
+use crate::transform::*;
+use crate::sampler::*;
 
 #[derive(Clone)]
 pub struct Onb {
@@ -8,8 +14,11 @@ pub struct Onb {
 
 impl Onb {
     pub fn random_cosine_direction() -> V3 {
-        let r1 = rand::random::<f32>();
-        let r2 = rand::random::<f32>();
+        Onb::cosine_direction_from((random(), random()))
+    }
+
+    pub fn cosine_direction_from(r: (f32, f32)) -> V3 {
+        let (r1, r2) = r;
         let z = (1.0 - r2).sqrt();
         let phi = 2.0 * std::f32::consts::PI * r1;
         let x = phi.cos() * r2.sqrt();
@@ -17,6 +26,12 @@ impl Onb {
         V3(x,y,z)
     }
 
+    // n cosine distributed directions around +z whose inputs are stratified, so they cover the hemisphere more
+    // evenly than independent samples; map them with local like random_cosine_direction
+    pub fn stratified_cosine_directions<R: Rng>(n: usize, rng: &mut R) -> Vec<V3> {
+        stratified_2d(n, rng).into_iter().map(Onb::cosine_direction_from).collect()
+    }
+
     pub fn new_from_w(n: &V3) -> Onb {
         let w = n.normalize();
         let a = if w.x().abs() > 0.9 {
@@ -56,7 +71,7 @@ pub struct Aabb {
 }
 
 impl Aabb {
-    fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> bool {
+    pub fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> bool {
         let invD = 1.0 / ray.direction.x();
         let mut t0 = (self.min.0 - ray.origin.0) * invD;
         let mut t1 = (self.max.0 - ray.origin.0) * invD;
@@ -105,6 +120,21 @@ impl Aabb {
         true
     }
 
+    pub fn new(min: V3, max: V3) -> Aabb {
+        Aabb {
+            min: min,
+            max: max,
+        }
+    }
+
+    pub fn min(&self) -> V3 {
+        self.min
+    }
+
+    pub fn max(&self) -> V3 {
+        self.max
+    }
+
     pub fn surround(&self, other: &Aabb) -> Aabb {
         Aabb {
             min: V3(
@@ -119,9 +149,100 @@ impl Aabb {
             ),
         }
     }
+
+    // boxes are closed, so ones that only share a face, edge or corner overlap too
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        self.min.x() <= other.max.x() && other.min.x() <= self.max.x()
+            && self.min.y() <= other.max.y() && other.min.y() <= self.max.y()
+            && self.min.z() <= other.max.z() && other.min.z() <= self.max.z()
+    }
+
+    // for touching boxes this is flat along the axes they touch on
+    pub fn intersection(&self, other: &Aabb) -> Option<Aabb> {
+        if !self.overlaps(other) {
+            return None;
+        }
+
+        Some(Aabb {
+            min: V3(
+                self.min.x().max(other.min.x()),
+                self.min.y().max(other.min.y()),
+                self.min.z().max(other.min.z()),
+            ),
+            max: V3(
+                self.max.x().min(other.max.x()),
+                self.max.y().min(other.max.y()),
+                self.max.z().min(other.max.z()),
+            ),
+        })
+    }
+}
+
+// a perturbed shading normal can leave the viewer below its hemisphere although the ray hit the front of the
+// geometry, which turns grazing hits black; bend it just far enough that the viewer is above it again.
+// front_face tells which side of the geometric surface the ray arrived on, the normal keeps its orientation
+pub fn adapt_shading_normal(normal: V3, front_face: bool, ray: &Ray) -> V3 {
+    let side = if front_face { 1.0 } else { -1.0 };
+    let facing = normal.scale(side);
+    let outgoing = -ray.direction.as_V3();
+    let cos = outgoing.dot(facing);
+    if cos >= 1e-3 {
+        return normal;
+    }
+
+    (facing + outgoing.scale(1e-3 - cos)).normalize().scale(side)
 }
 
-trait Hit {
+/// The extension point for primitives defined outside this crate.
+///
+/// A downstream type implements `hit` and `bounding_box`, then becomes a figure through `Figures::custom` or any
+/// `Transformable` method, after which scenes, BVHs and transforms treat it like a built-in primitive. `hit`
+/// reports the nearest intersection with `at` in `(tmin, tmax)`; `HitRecord::set_face_normal` orients the normal
+/// and sets `front_face` from the outward normal. `bounding_box` returns `None` only for unbounded figures,
+/// which every ray is then tested against. The sampling methods matter only for figures used as light shapes:
+/// `random_from` maps a point of `[0, 1)^2` to a direction from `o` towards the surface and `pdf_value` gives
+/// the solid angle density of that direction.
+///
+/// Figures are shared across render threads, so implementors are `Send + Sync`.
+///
+/// ```
+/// use ruyt::*;
+///
+/// // the plane y = 0
+/// struct Floor;
+///
+/// impl Hit for Floor {
+///     fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
+///         let t = -ray.origin.y() / ray.direction.y();
+///         if !(t > tmin && t < tmax) {
+///             return None;
+///         }
+///
+///         let point = ray.extend_at(t);
+///         let mut rec = HitRecord {
+///             at: t,
+///             point: point,
+///             normal: V3(0.0, 1.0, 0.0),
+///             front_face: true,
+///             emission: V3(0.0, 0.0, 0.0),
+///             color: None,
+///             time: ray.time,
+///             wavelength: ray.wavelength,
+///             u: point.x(),
+///             v: point.z(),
+///         };
+///         rec.set_face_normal(ray, V3U::from_normalized_unchecked(V3(0.0, 1.0, 0.0)));
+///         Some(rec)
+///     }
+///
+///     fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<Aabb> {
+///         None
+///     }
+/// }
+///
+/// let floor = Floor.translated(V3(0.0, -1.0, 0.0));
+/// ```
+pub trait Hit: Send + Sync {
     fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord>;
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<Aabb>;
 
@@ -132,6 +253,10 @@ trait Hit {
     fn random(&self, o: V3) -> V3 {
         V3(1.0, 0.0, 0.0)
     }
+
+    fn random_from(&self, o: V3, _r: (f32, f32)) -> V3 {
+        self.random(o)
+    }
 }
 
 #[derive(Clone)]
@@ -141,9 +266,8 @@ pub struct Sphere {
 }
 
 impl Sphere {
-    fn random_to_sphere(radius: f32, distance_squared: f32) -> V3 {
-        let r1 = rand::random::<f32>();
-        let r2 = rand::random::<f32>();
+    fn random_to_sphere(radius: f32, distance_squared: f32, r: (f32, f32)) -> V3 {
+        let (r1, r2) = r;
         let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
         let phi = 2.0 * std::f32::consts::PI * r1;
         let x = phi.cos() * (1.0 - z * z).sqrt();
@@ -164,20 +288,27 @@ impl Hit for Sphere {
             let check = |at| {
                 if tmin < at && at < tmax {
                     let point = ray.extend_at(at);
+                    let normal = (point - self.center).scale(1.0 / self.radius);
 
                     Some(HitRecord {
                         at: at,
                         point: point,
-                        normal: (point - self.center).scale(1.0 / self.radius),
+                        normal: normal,
                         u: 1.0,
                         v: 1.0,
+                        front_face: ray.direction.dot(normal) < 0.0,
+                        emission: V3(0.0, 0.0, 0.0),
+                        color: None,
+                        time: ray.time,
+                        wavelength: ray.wavelength,
                     })
                 } else {
                     None
                 }
             };
 
-            check((-b - discriminant.sqrt()) / a).or(check((-b + discriminant.sqrt()) / a))
+            let root = discriminant.sqrt();
+            check((-b - root) / a).or_else(|| check((-b + root) / a))
         } else {
             None
         }
@@ -191,7 +322,7 @@ impl Hit for Sphere {
     }
 
     fn pdf_value(&self, o: V3, v: V3U) -> f32 {
-        match self.hit(&Ray { origin: o, direction: v }, 0.001, std::f32::MAX) {
+        match self.hit(&Ray { origin: o, direction: v, time: 0.0, wavelength: None }, 0.001, std::f32::MAX) {
             Some(rec) => {
                 let cos_theta_max = (1.0 - self.radius * self.radius / (self.center - o).square_norm()).sqrt();
                 let solid_angle = 2.0 * std::f32::consts::PI * (1.0 - cos_theta_max * cos_theta_max);
@@ -202,15 +333,19 @@ impl Hit for Sphere {
     }
 
     fn random(&self, o: V3) -> V3 {
+        self.random_from(o, (random(), random()))
+    }
+
+    fn random_from(&self, o: V3, r: (f32, f32)) -> V3 {
         let direction = self.center - o;
         let distance_squared = direction.square_norm();
         let uvw = Onb::new_from_w(&direction);
-        uvw.local(&Sphere::random_to_sphere(self.radius, distance_squared))
+        uvw.local(&Sphere::random_to_sphere(self.radius, distance_squared, r))
     }
 }
 
 #[derive(Clone)]
-struct XYRect {
+pub struct XYRect {
     x0: f32,
     x1: f32,
     y0: f32,
@@ -219,6 +354,7 @@ struct XYRect {
 }
 
 impl Hit for XYRect {
+    // the normal is turned towards the ray; front_face tells whether the ray came from the +z side
     fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
         let t = (self.k - ray.origin.z()) / ray.direction.z();
         if t < tmin || t > tmax {
@@ -231,13 +367,20 @@ impl Hit for XYRect {
             return None;
         }
 
-        Some(HitRecord {
+        let mut rec = HitRecord {
             at: t,
             point: ray.extend_at(t),
             normal: V3(0.0, 0.0, 1.0),
+            front_face: true,
+            emission: V3(0.0, 0.0, 0.0),
+            color: None,
+            time: ray.time,
+            wavelength: ray.wavelength,
             u: (x - self.x0) / (self.x1 - self.x0),
             v: (y - self.y0) / (self.y1 - self.y0),
-        })
+        };
+        rec.set_face_normal(ray, V3U::from_normalized_unchecked(V3(0.0, 0.0, 1.0)));
+        Some(rec)
     }
 
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<Aabb> {
@@ -249,7 +392,7 @@ impl Hit for XYRect {
 }
 
 #[derive(Clone)]
-struct YZRect {
+pub struct YZRect {
     y0: f32,
     y1: f32,
     z0: f32,
@@ -270,13 +413,20 @@ impl Hit for YZRect {
             return None;
         }
 
-        Some(HitRecord {
+        let mut rec = HitRecord {
             at: t,
             point: ray.extend_at(t),
             normal: V3(1.0, 0.0, 0.0),
+            front_face: true,
+            emission: V3(0.0, 0.0, 0.0),
+            color: None,
+            time: ray.time,
+            wavelength: ray.wavelength,
             u: (y - self.y0) / (self.y1 - self.y0),
             v: (z - self.z0) / (self.z1 - self.z0),
-        })
+        };
+        rec.set_face_normal(ray, V3U::from_normalized_unchecked(V3(1.0, 0.0, 0.0)));
+        Some(rec)
     }
 
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<Aabb> {
@@ -288,7 +438,7 @@ impl Hit for YZRect {
 }
 
 #[derive(Clone)]
-struct XZRect {
+pub struct XZRect {
     x0: f32,
     x1: f32,
     z0: f32,
@@ -309,13 +459,20 @@ impl Hit for XZRect {
             return None;
         }
 
-        Some(HitRecord {
+        let mut rec = HitRecord {
             at: t,
             point: ray.extend_at(t),
             normal: V3(0.0, 1.0, 0.0),
+            front_face: true,
+            emission: V3(0.0, 0.0, 0.0),
+            color: None,
+            time: ray.time,
+            wavelength: ray.wavelength,
             u: (x - self.x0) / (self.x1 - self.x0),
             v: (z - self.z0) / (self.z1 - self.z0),
-        })
+        };
+        rec.set_face_normal(ray, V3U::from_normalized_unchecked(V3(0.0, 1.0, 0.0)));
+        Some(rec)
     }
 
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<Aabb> {
@@ -326,10 +483,14 @@ impl Hit for XZRect {
     }
 
     fn pdf_value(&self, o: V3, v: V3U) -> f32 {
-        match self.hit(&Ray { origin: o, direction: v }, 0.001, std::f32::MAX) {
+        match self.hit(&Ray { origin: o, direction: v, time: 0.0, wavelength: None }, 0.001, std::f32::MAX) {
             Some(rec) => {
                 let area = (self.x1 - self.x0) * (self.z1 - self.z0);
                 let cosine = v.dot(rec.normal).abs();
+                // seen edge-on the light has no projected area and can't be reached by a sample
+                if cosine * area < 1e-6 || !rec.at.is_finite() {
+                    return 0.0;
+                }
                 rec.at * rec.at / (cosine * area)
             },
             None => 0.0,
@@ -337,19 +498,290 @@ impl Hit for XZRect {
     }
 
     fn random(&self, o: V3) -> V3 {
-        V3(self.x0 + rand::random::<f32>() * (self.x1 - self.x0), self.k, self.z0 + rand::random::<f32>() * (self.z1 - self.z0)) - o
+        self.random_from(o, (random(), random()))
+    }
+
+    fn random_from(&self, o: V3, r: (f32, f32)) -> V3 {
+        V3(self.x0 + r.0 * (self.x1 - self.x0), self.k, self.z0 + r.1 * (self.z1 - self.z0)) - o
+    }
+}
+
+// unbounded, so a scene tests it against every ray; u and v are distances in world units along two axes of the
+// plane from point, which tile a uv_checker or a wrapping image texture across it
+#[derive(Clone)]
+pub struct Plane {
+    point: V3,
+    normal: V3,
+    u_axis: V3,
+    v_axis: V3,
+}
+
+impl Hit for Plane {
+    fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
+        let denom = ray.direction.dot(self.normal);
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+
+        let t = (self.point - ray.origin).dot(self.normal) / denom;
+        if t < tmin || t > tmax {
+            return None;
+        }
+
+        let point = ray.extend_at(t);
+        let offset = point - self.point;
+        Some(HitRecord {
+            at: t,
+            point: point,
+            normal: self.normal,
+            u: offset.dot(self.u_axis),
+            v: offset.dot(self.v_axis),
+            front_face: denom < 0.0,
+            emission: V3(0.0, 0.0, 0.0),
+            color: None,
+            time: ray.time,
+            wavelength: ray.wavelength,
+        })
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<Aabb> {
+        None
+    }
+}
+
+#[derive(Clone)]
+pub struct Triangle {
+    v0: V3,
+    v1: V3,
+    v2: V3,
+    normals: Option<(V3, V3, V3)>,
+    texcoords: Option<((f32, f32), (f32, f32), (f32, f32))>,
+    colors: Option<(V3, V3, V3)>,
+    double_sided: bool,
+}
+
+impl Triangle {
+    pub fn new(v0: V3, v1: V3, v2: V3) -> Triangle {
+        Triangle {
+            v0: v0,
+            v1: v1,
+            v2: v2,
+            normals: None,
+            texcoords: None,
+            colors: None,
+            double_sided: false,
+        }
+    }
+
+    pub fn with_normals(mut self, n0: V3, n1: V3, n2: V3) -> Triangle {
+        self.normals = Some((n0.normalize(), n1.normalize(), n2.normalize()));
+        self
+    }
+
+    pub fn with_texcoords(mut self, t0: (f32, f32), t1: (f32, f32), t2: (f32, f32)) -> Triangle {
+        self.texcoords = Some((t0, t1, t2));
+        self
+    }
+
+    pub fn with_colors(mut self, c0: V3, c1: V3, c2: V3) -> Triangle {
+        self.colors = Some((c0, c1, c2));
+        self
+    }
+
+    pub fn double_sided(mut self) -> Triangle {
+        self.double_sided = true;
+        self
+    }
+}
+
+impl Hit for Triangle {
+    fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
+        let e1 = self.v1 - self.v0;
+        let e2 = self.v2 - self.v0;
+        let pvec = ray.direction.as_V3().cross(e2);
+        let det = e1.dot(pvec);
+        if det.abs() < 1e-8 {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let tvec = ray.origin - self.v0;
+        let u = tvec.dot(pvec) * inv_det;
+        if u < 0.0 || u > 1.0 {
+            return None;
+        }
+
+        let qvec = tvec.cross(e1);
+        let v = ray.direction.dot(qvec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = e2.dot(qvec) * inv_det;
+        if t < tmin || t > tmax {
+            return None;
+        }
+
+        let geometric = e1.cross(e2).normalize();
+        let front_face = ray.direction.dot(geometric) < 0.0;
+        let normal = match self.normals {
+            Some((n0, n1, n2)) => {
+                let interpolated = (n0.scale(1.0 - u - v) + n1.scale(u) + n2.scale(v)).normalize();
+                adapt_shading_normal(interpolated, front_face, ray)
+            },
+            None => geometric,
+        };
+
+        let (tu, tv) = match self.texcoords {
+            Some((t0, t1, t2)) => (
+                t0.0 * (1.0 - u - v) + t1.0 * u + t2.0 * v,
+                t0.1 * (1.0 - u - v) + t1.1 * u + t2.1 * v,
+            ),
+            None => (u, v),
+        };
+
+        Some(HitRecord {
+            at: t,
+            point: ray.extend_at(t),
+            normal: if self.double_sided && !front_face { -normal } else { normal },
+            u: tu,
+            v: tv,
+            front_face: front_face,
+            emission: V3(0.0, 0.0, 0.0),
+            color: self.colors.map(|(c0, c1, c2)| c0.scale(1.0 - u - v) + c1.scale(u) + c2.scale(v)),
+            time: ray.time,
+            wavelength: ray.wavelength,
+        })
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<Aabb> {
+        Some(Aabb {
+            min: V3(
+                self.v0.x().min(self.v1.x()).min(self.v2.x()) - 0.0001,
+                self.v0.y().min(self.v1.y()).min(self.v2.y()) - 0.0001,
+                self.v0.z().min(self.v1.z()).min(self.v2.z()) - 0.0001,
+            ),
+            max: V3(
+                self.v0.x().max(self.v1.x()).max(self.v2.x()) + 0.0001,
+                self.v0.y().max(self.v1.y()).max(self.v2.y()) + 0.0001,
+                self.v0.z().max(self.v1.z()).max(self.v2.z()) + 0.0001,
+            ),
+        })
+    }
+}
+
+// the parallelogram corner + s * u + t * v for s, t in [0, 1], facing u x v; unlike the axis-aligned rects it
+// takes any orientation, and as a light it is sampled uniformly over its area
+#[derive(Clone)]
+pub struct Quad {
+    corner: V3,
+    u: V3,
+    v: V3,
+    double_sided: bool,
+}
+
+impl Quad {
+    pub fn new(corner: V3, u: V3, v: V3) -> Quad {
+        Quad {
+            corner: corner,
+            u: u,
+            v: v,
+            double_sided: false,
+        }
+    }
+
+    pub fn double_sided(mut self) -> Quad {
+        self.double_sided = true;
+        self
+    }
+
+    fn area(&self) -> f32 {
+        self.u.cross(self.v).norm()
+    }
+}
+
+impl Hit for Quad {
+    fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
+        let n = self.u.cross(self.v);
+        let denom = n.dot(ray.direction);
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+
+        let t = n.dot(self.corner - ray.origin) / denom;
+        if t < tmin || t > tmax {
+            return None;
+        }
+
+        // the hit point's coordinates along u and v, through the dual basis of the plane
+        let point = ray.extend_at(t);
+        let w = n.scale(1.0 / n.dot(n));
+        let p = point - self.corner;
+        let (a, b) = (w.dot(p.cross(self.v)), w.dot(self.u.cross(p)));
+        if a < 0.0 || a > 1.0 || b < 0.0 || b > 1.0 {
+            return None;
+        }
+
+        let normal = n.normalize();
+        let front_face = denom < 0.0;
+        Some(HitRecord {
+            at: t,
+            point: point,
+            normal: if self.double_sided && !front_face { -normal } else { normal },
+            u: a,
+            v: b,
+            front_face: front_face,
+            emission: V3(0.0, 0.0, 0.0),
+            color: None,
+            time: ray.time,
+            wavelength: ray.wavelength,
+        })
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<Aabb> {
+        let corners = [self.corner, self.corner + self.u, self.corner + self.v, self.corner + self.u + self.v];
+        let pad = V3(0.0001, 0.0001, 0.0001);
+        let min = corners.iter().fold(corners[0], |m, c| V3(m.x().min(c.x()), m.y().min(c.y()), m.z().min(c.z())));
+        let max = corners.iter().fold(corners[0], |m, c| V3(m.x().max(c.x()), m.y().max(c.y()), m.z().max(c.z())));
+        Some(Aabb { min: min - pad, max: max + pad })
+    }
+
+    fn pdf_value(&self, o: V3, v: V3U) -> f32 {
+        match self.hit(&Ray { origin: o, direction: v, time: 0.0, wavelength: None }, 0.001, std::f32::MAX) {
+            Some(rec) => {
+                let cosine = v.dot(rec.normal).abs();
+                // seen edge-on the light has no projected area and can't be reached by a sample
+                if cosine * self.area() < 1e-6 || !rec.at.is_finite() {
+                    return 0.0;
+                }
+                rec.at * rec.at / (cosine * self.area())
+            },
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, o: V3) -> V3 {
+        self.random_from(o, (random(), random()))
+    }
+
+    fn random_from(&self, o: V3, r: (f32, f32)) -> V3 {
+        self.corner + self.u.scale(r.0) + self.v.scale(r.1) - o
     }
 }
 
 #[derive(Clone)]
-struct FlipNormals {
+pub struct FlipNormals {
     figure: Box<Figures>,
 }
 
 impl Hit for FlipNormals {
+    // swaps which side is the front and leaves the normal facing the ray, whether or not the wrapped figure had
+    // turned it that way
     fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
         self.figure.hit(ray, tmin, tmax).map(|mut rec| {
-            rec.normal = -rec.normal;
+            let facing = ray.direction.dot(rec.normal) < 0.0;
+            let outward_normal = if rec.front_face == facing { rec.normal } else { -rec.normal };
+            rec.set_face_normal(ray, V3U::from_normalized_unchecked(-outward_normal));
             rec
         })
     }
@@ -360,7 +792,7 @@ impl Hit for FlipNormals {
 }
 
 #[derive(Clone)]
-struct Cuboid {
+pub struct Cuboid {
     pmin: V3,
     pmax: V3,
     figure: Box<Figures>,
@@ -384,6 +816,7 @@ impl Cuboid {
 }
 
 impl Hit for Cuboid {
+    // the faces turn their normals towards the ray, so hits from inside shade correctly
     fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
         self.figure.hit(ray, tmin, tmax)
     }
@@ -397,14 +830,14 @@ impl Hit for Cuboid {
 }
 
 #[derive(Clone)]
-struct Translate {
+pub struct Translate {
     offset: V3,
     figure: Box<Figures>,
 }
 
 impl Hit for Translate {
     fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
-        let moved_ray = Ray { origin: ray.origin - self.offset, direction: ray.direction };
+        let moved_ray = Ray { origin: ray.origin - self.offset, direction: ray.direction, time: ray.time, wavelength: ray.wavelength };
         self.figure.hit(&moved_ray, tmin, tmax).map(|mut rec| {
             rec.point = rec.point + self.offset;
             rec
@@ -412,18 +845,17 @@ impl Hit for Translate {
     }
 
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<Aabb> {
-        self.figure.bounding_box(t0, t1).map(|mut bbox| {
-            bbox = Aabb {
-                min: bbox.min,
-                max: bbox.max,
-            };
-            bbox
+        self.figure.bounding_box(t0, t1).map(|bbox| {
+            Aabb {
+                min: bbox.min + self.offset,
+                max: bbox.max + self.offset,
+            }
         })
     }
 }
 
 #[derive(Clone)]
-struct RotateY {
+pub struct RotateY {
     sin_theta: f32,
     cos_theta: f32,
     figure: Box<Figures>,
@@ -474,39 +906,153 @@ impl RotateY {
 
 impl Hit for RotateY {
     fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
-        let mut origin = ray.origin;
-        origin.0 = self.cos_theta * ray.origin.0 - self.sin_theta * ray.origin.2;
-        origin.2 = self.sin_theta * ray.origin.0 + self.cos_theta * ray.origin.2;
-        let rotated_r = Ray {
-            origin: origin,
-            direction: V3U::new(V3(
-                self.cos_theta * ray.direction.x() - self.sin_theta * ray.direction.z(),
-                ray.direction.y(),
-                self.sin_theta * ray.direction.x() + self.cos_theta * ray.direction.z(),
-            ))
+        hit_rotated_y(&self.figure, self.sin_theta, self.cos_theta, ray, tmin, tmax)
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<Aabb> {
+        Some(self.bbox.clone())
+    }
+}
+
+fn hit_rotated_y(figure: &Figures, sin_theta: f32, cos_theta: f32, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
+    let mut origin = ray.origin;
+    origin.0 = cos_theta * ray.origin.0 - sin_theta * ray.origin.2;
+    origin.2 = sin_theta * ray.origin.0 + cos_theta * ray.origin.2;
+    let rotated_r = Ray {
+        origin: origin,
+        direction: V3U::from_normalized_unchecked(V3(
+            cos_theta * ray.direction.x() - sin_theta * ray.direction.z(),
+            ray.direction.y(),
+            sin_theta * ray.direction.x() + cos_theta * ray.direction.z(),
+        )),
+        time: ray.time,
+        wavelength: ray.wavelength,
+    };
+
+    figure.hit(&rotated_r, tmin, tmax).map(|mut rec| {
+        let mut point = rec.point;
+        let mut normal = rec.normal;
+        point.0 = cos_theta * rec.point.0 + sin_theta * rec.point.2;
+        point.2 = - sin_theta * rec.point.0 + cos_theta * rec.point.2;
+        normal.0 = cos_theta * rec.normal.0 + sin_theta * rec.normal.2;
+        normal.2 = - sin_theta * rec.normal.0 + cos_theta * rec.normal.2;
+        rec.point = point;
+        rec.normal = normal;
+        rec
+    })
+}
+
+// a rotation about y that turns from angle0 at time 0 to angle1 at time 1 (in degrees), for motion blur; times
+// outside [0, 1] hold the end angles
+#[derive(Clone)]
+pub struct RotateYMotion {
+    angle0: f32,
+    angle1: f32,
+    figure: Box<Figures>,
+    bbox: Aabb,
+}
+
+impl RotateYMotion {
+    fn new(angle0: f32, angle1: f32, figure: Figures) -> RotateYMotion {
+        let (theta0, theta1) = (angle0.to_radians(), angle1.to_radians());
+        let quarter = std::f32::consts::FRAC_PI_2;
+
+        // every corner moves on a circle about the y axis, at the angle phi - theta; the arc it sweeps reaches
+        // furthest out at its ends and where it crosses an axis
+        let bbox = figure.bounding_box(0.0, 1.0).unwrap();
+        let mut min = V3(std::f32::MAX, bbox.min.y(), std::f32::MAX);
+        let mut max = V3(-std::f32::MAX, bbox.max.y(), -std::f32::MAX);
+        for &x in [bbox.min.x(), bbox.max.x()].iter() {
+            for &z in [bbox.min.z(), bbox.max.z()].iter() {
+                let (radius, phi) = ((x * x + z * z).sqrt(), z.atan2(x));
+                let (lo, hi) = ((phi - theta0).min(phi - theta1), (phi - theta0).max(phi - theta1));
+                let crossings = ((lo / quarter).ceil() as i32..=(hi / quarter).floor() as i32).map(|k| k as f32 * quarter);
+
+                for angle in vec![lo, hi].into_iter().chain(crossings) {
+                    let (newx, newz) = (radius * angle.cos(), radius * angle.sin());
+                    min = V3(min.x().min(newx), min.y(), min.z().min(newz));
+                    max = V3(max.x().max(newx), max.y(), max.z().max(newz));
+                }
+            }
+        }
+
+        RotateYMotion {
+            angle0: angle0,
+            angle1: angle1,
+            figure: Box::new(figure),
+            bbox: Aabb { min: min, max: max },
+        }
+    }
+}
+
+impl Hit for RotateYMotion {
+    fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
+        let t = ray.time.max(0.0).min(1.0);
+        let radians = (self.angle0 + (self.angle1 - self.angle0) * t).to_radians();
+        hit_rotated_y(&self.figure, radians.sin(), radians.cos(), ray, tmin, tmax)
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<Aabb> {
+        Some(self.bbox.clone())
+    }
+}
+
+// the wrapped figure is shared, so instances of one mesh under different transforms don't copy its geometry
+#[derive(Clone)]
+pub struct Transformed {
+    transform: Transform,
+    figure: Arc<Figures>,
+}
+
+impl Hit for Transformed {
+    fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
+        // the object space direction is renormalized, so ray parameters are rescaled by its length
+        let direction = self.transform.inverse_vector(ray.direction.as_V3());
+        let length = direction.norm();
+        let local_ray = Ray {
+            origin: self.transform.inverse_point(ray.origin),
+            direction: V3U::new(direction),
+            time: ray.time,
+            wavelength: ray.wavelength,
         };
 
-        self.figure.hit(&rotated_r, tmin, tmax).map(|mut rec| {
-            let mut point = rec.point;
-            let mut normal = rec.normal;
-            point.0 = self.cos_theta * rec.point.0 + self.sin_theta * rec.point.2;
-            point.2 = - self.sin_theta * rec.point.0 + self.cos_theta * rec.point.2;
-            normal.0 = self.cos_theta * rec.normal.0 + self.sin_theta * rec.normal.2;
-            normal.2 = - self.sin_theta * rec.normal.0 + self.cos_theta * rec.normal.2;
-            rec.point = point;
-            rec.normal = normal;
+        self.figure.hit(&local_ray, tmin * length, tmax * length).map(|mut rec| {
+            // front_face is decided again against the world space outward normal, so it holds under transforms
+            // that mirror as well
+            let facing = local_ray.direction.dot(rec.normal) < 0.0;
+            let outward_normal = self.transform.normal(if rec.front_face == facing { rec.normal } else { -rec.normal });
+            rec.at = rec.at / length;
+            rec.point = self.transform.point(rec.point);
+            rec.normal = self.transform.normal(rec.normal);
+            rec.front_face = ray.direction.dot(outward_normal) < 0.0;
             rec
         })
     }
 
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<Aabb> {
-        Some(self.bbox.clone())
+        self.figure.bounding_box(t0, t1).map(|bbox| {
+            let mut min = V3(std::f32::MAX, std::f32::MAX, std::f32::MAX);
+            let mut max = V3(-std::f32::MAX, -std::f32::MAX, -std::f32::MAX);
+            for i in 0..8 {
+                let corner = self.transform.point(V3(
+                    if i & 1 == 0 { bbox.min.x() } else { bbox.max.x() },
+                    if i & 2 == 0 { bbox.min.y() } else { bbox.max.y() },
+                    if i & 4 == 0 { bbox.min.z() } else { bbox.max.z() },
+                ));
+                min = V3(min.x().min(corner.x()), min.y().min(corner.y()), min.z().min(corner.z()));
+                max = V3(max.x().max(corner.x()), max.y().max(corner.y()), max.z().max(corner.z()));
+            }
+
+            Aabb { min: min, max: max }
+        })
     }
 }
 
 #[derive(Clone)]
-struct ConstantMedium {
+pub struct ConstantMedium {
     density: f32,
+    // radiance added per unit length travelled through the medium
+    emission: V3,
     boundary: Box<Figures>,
 }
 
@@ -529,7 +1075,7 @@ impl Hit for ConstantMedium {
                     rec1.at = 0.0;
                 }
                 let distance_inside_boundary = rec2.at - rec1.at;
-                let hit_distance = - (1.0 / self.density) * rand::random::<f32>().log(std::f32::consts::E);
+                let hit_distance = - (1.0 / self.density) * random().log(std::f32::consts::E);
                 if hit_distance < distance_inside_boundary {
                     let at = rec1.at + hit_distance;
 
@@ -539,6 +1085,11 @@ impl Hit for ConstantMedium {
                         normal: V3(1.0, 0.0, 0.0),
                         u: 0.0,
                         v: 0.0,
+                        front_face: true,
+                        emission: self.emission.scale(1.0 / self.density),
+                        color: None,
+                        time: ray.time,
+                        wavelength: ray.wavelength,
                     });
                 }
             }
@@ -553,15 +1104,24 @@ impl Hit for ConstantMedium {
 }
 
 #[derive(Clone)]
-struct BvhNode {
+pub struct BvhNode {
     bbox: Aabb,
     left: Box<Figures>,
     right: Box<Figures>
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum BvhSplit {
+    // halves the primitives sorted along the axis
+    ObjectMedian,
+    // splits the node's box at its midpoint and partitions primitives by centroid; falls back to the object
+    // median when every centroid lands on one side
+    SpatialMedian,
+}
+
 impl BvhNode {
-    fn new(mut figures: Vec<Figures>, time0: f32, time1: f32) -> BvhNode {
-        let axis = (3.0 * rand::random::<f32>()) as i32;
+    fn new(mut figures: Vec<Figures>, time0: f32, time1: f32, split: BvhSplit) -> BvhNode {
+        let axis = (3.0 * random()) as i32;
 
         if axis == 0 {
             figures.sort_by(BvhNode::box_x_compare);
@@ -579,10 +1139,14 @@ impl BvhNode {
             } else if n == 2 {
                 (figures[0].clone(), figures[1].clone())
             } else {
-                let (former, latter) = figures.split_at(n / 2);
+                let mid = match split {
+                    BvhSplit::ObjectMedian => n / 2,
+                    BvhSplit::SpatialMedian => BvhNode::spatial_median(&mut figures, axis, time0, time1),
+                };
+                let (former, latter) = figures.split_at(mid);
                 (
-                    Figures::bvh_node(former.to_vec(), time0, time1),
-                    Figures::bvh_node(latter.to_vec(), time0, time1),
+                    Figures::bvh_node_with(former.to_vec(), time0, time1, split),
+                    Figures::bvh_node_with(latter.to_vec(), time0, time1, split),
                 )
             };
 
@@ -593,6 +1157,28 @@ impl BvhNode {
         }
     }
 
+    // moves the figures whose centroid lies below the midpoint to the front and returns how many there are
+    fn spatial_median(figures: &mut Vec<Figures>, axis: i32, time0: f32, time1: f32) -> usize {
+        let component = |v: V3| if axis == 0 { v.x() } else if axis == 1 { v.y() } else { v.z() };
+        let centroids = figures.iter().map(|f| {
+            let bbox = f.bounding_box(time0, time1).unwrap();
+            component((bbox.min + bbox.max).scale(0.5))
+        }).collect::<Vec<f32>>();
+
+        let lo = centroids.iter().cloned().fold(std::f32::MAX, f32::min);
+        let hi = centroids.iter().cloned().fold(-std::f32::MAX, f32::max);
+        let midpoint = 0.5 * (lo + hi);
+        let mid = centroids.iter().filter(|c| **c < midpoint).count();
+        if mid == 0 || mid == figures.len() {
+            return figures.len() / 2;
+        }
+
+        let (mut below, above): (Vec<_>, Vec<_>) = figures.drain(..).zip(centroids).partition(|(_, c)| *c < midpoint);
+        below.extend(above);
+        figures.extend(below.into_iter().map(|(f, _)| f));
+        mid
+    }
+
     fn box_x_compare(left: &Figures, right: &Figures) -> ::std::cmp::Ordering {
         left.bounding_box(0.0, 0.0).unwrap().min.x().partial_cmp(&right.bounding_box(0.0, 0.0).unwrap().min.x()).unwrap_or(::std::cmp::Ordering::Equal)
     }
@@ -635,19 +1221,119 @@ impl Hit for BvhNode {
     }
 }
 
+// a list of trait objects, for code that prefers open extension over the Figures enum; every hit goes through a
+// vtable call and can't be inlined, so expect it to run a few percent slower than the equivalent Figures::Figures
+pub struct DynFigures {
+    figures: Vec<Box<dyn Hit + Send + Sync>>,
+}
+
+impl DynFigures {
+    pub fn new() -> DynFigures {
+        DynFigures {
+            figures: vec![],
+        }
+    }
+
+    pub fn push<H: Hit + 'static>(&mut self, figure: H) {
+        self.figures.push(Box::new(figure));
+    }
+
+    pub fn len(&self) -> usize {
+        self.figures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.figures.is_empty()
+    }
+}
+
+impl Default for DynFigures {
+    fn default() -> DynFigures {
+        DynFigures::new()
+    }
+}
+
+impl Hit for DynFigures {
+    fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
+        let mut closest_parameter = tmax;
+        let mut record = None;
+
+        for object in &self.figures {
+            if let Some(rec) = object.hit(ray, tmin, closest_parameter) {
+                closest_parameter = rec.at;
+                record = Some(rec);
+            }
+        }
+
+        record
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<Aabb> {
+        let mut bbox: Option<Aabb> = None;
+        for f in &self.figures {
+            let b = f.bounding_box(t0, t1)?;
+            bbox = Some(match bbox {
+                Some(acc) => acc.surround(&b),
+                None => b,
+            });
+        }
+
+        bbox
+    }
+
+    fn pdf_value(&self, o: V3, v: V3U) -> f32 {
+        let weight = 1.0 / self.figures.len() as f32;
+        self.figures.iter().map(|object| weight * object.pdf_value(o, v)).sum()
+    }
+
+    fn random(&self, o: V3) -> V3 {
+        if self.figures.is_empty() {
+            return V3(1.0, 0.0, 0.0);
+        }
+
+        let index = (random() * self.figures.len() as f32) as usize;
+        self.figures[index.min(self.figures.len() - 1)].random(o)
+    }
+
+    fn random_from(&self, o: V3, r: (f32, f32)) -> V3 {
+        if self.figures.is_empty() {
+            return V3(1.0, 0.0, 0.0);
+        }
+
+        let scaled = r.0 * self.figures.len() as f32;
+        let index = (scaled as usize).min(self.figures.len() - 1);
+        self.figures[index].random_from(o, (scaled - index as f32, r.1))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FigureIssue {
+    NonFiniteCoordinate,
+    ZeroRadiusSphere,
+    DegenerateRect,
+    DegenerateTriangle,
+    NonPositiveDensity,
+}
+
 #[derive(Clone)]
 pub enum Figures {
     Sphere(Sphere),
     XYRect(XYRect),
     YZRect(YZRect),
     XZRect(XZRect),
+    Triangle(Triangle),
+    Quad(Quad),
+    Plane(Plane),
     FlipNormals(FlipNormals),
     Cuboid(Cuboid),
     Translate(Translate),
     RotateY(RotateY),
+    RotateYMotion(RotateYMotion),
+    Transformed(Transformed),
     ConstantMedium(ConstantMedium),
     Figures(Vec<Figures>),
     BvhNode(BvhNode),
+    Custom(Arc<dyn Hit + Send + Sync>),
 }
 
 impl Figures {
@@ -688,6 +1374,45 @@ impl Figures {
         })
     }
 
+    pub fn triangle(v0: V3, v1: V3, v2: V3) -> Figures {
+        Figures::Triangle(Triangle::new(v0, v1, v2))
+    }
+
+    pub fn triangle_double_sided(v0: V3, v1: V3, v2: V3) -> Figures {
+        Figures::Triangle(Triangle::new(v0, v1, v2).double_sided())
+    }
+
+    pub fn triangle_smooth(v0: V3, v1: V3, v2: V3, n0: V3, n1: V3, n2: V3) -> Figures {
+        Figures::Triangle(Triangle::new(v0, v1, v2).with_normals(n0, n1, n2))
+    }
+
+    pub fn triangle_uv(v0: V3, v1: V3, v2: V3, t0: (f32, f32), t1: (f32, f32), t2: (f32, f32)) -> Figures {
+        Figures::Triangle(Triangle::new(v0, v1, v2).with_texcoords(t0, t1, t2))
+    }
+
+    pub fn triangle_colored(v0: V3, v1: V3, v2: V3, c0: V3, c1: V3, c2: V3) -> Figures {
+        Figures::Triangle(Triangle::new(v0, v1, v2).with_colors(c0, c1, c2))
+    }
+
+    pub fn quad(corner: V3, u: V3, v: V3) -> Figures {
+        Figures::Quad(Quad::new(corner, u, v))
+    }
+
+    pub fn quad_double_sided(corner: V3, u: V3, v: V3) -> Figures {
+        Figures::Quad(Quad::new(corner, u, v).double_sided())
+    }
+
+    // the infinite plane through point facing normal
+    pub fn plane(point: V3, normal: V3) -> Figures {
+        let onb = Onb::new_from_w(&normal);
+        Figures::Plane(Plane {
+            point: point,
+            normal: onb.w(),
+            u_axis: onb.u(),
+            v_axis: onb.v(),
+        })
+    }
+
     pub fn flip_normals(figure: Figures) -> Figures {
         Figures::FlipNormals(FlipNormals {
             figure: Box::new(figure),
@@ -698,6 +1423,10 @@ impl Figures {
         Figures::Cuboid(Cuboid::new(p0, p1))
     }
 
+    pub fn custom<H: Hit + 'static>(figure: H) -> Figures {
+        Figures::Custom(Arc::new(figure))
+    }
+
     pub fn translate(offset: V3, figure: Figures) -> Figures {
         Figures::Translate(Translate {
             offset: offset,
@@ -709,15 +1438,400 @@ impl Figures {
         Figures::RotateY(RotateY::new(angle, figure))
     }
 
+    // spins from angle0 to angle1 degrees over ray times 0 to 1; see Camera::with_shutter
+    pub fn rotate_y_motion(angle0: f32, angle1: f32, figure: Figures) -> Figures {
+        Figures::RotateYMotion(RotateYMotion::new(angle0, angle1, figure))
+    }
+
+    pub fn transform(transform: Transform, figure: Figures) -> Figures {
+        Figures::instance(Arc::new(figure), transform)
+    }
+
+    pub fn instance(shared: Arc<Figures>, transform: Transform) -> Figures {
+        Figures::Transformed(Transformed {
+            transform: transform,
+            figure: shared,
+        })
+    }
+
+    pub fn scale(factors: V3, figure: Figures) -> Figures {
+        Figures::transform(Transform::scale(factors), figure)
+    }
+
+    // collapses translate/rotate_y/transform wrappers into the geometry underneath wherever the hits stay the same:
+    // triangles take any orientation preserving transform, spheres, rects and cuboids only a translation and a
+    // (uniform for spheres) scale along the axes. Whatever can't be baked keeps its wrapper
+    pub fn bake_transform(self) -> Figures {
+        match self {
+            Figures::Translate(t) => {
+                let offset = t.offset;
+                let figure = t.figure.bake_transform();
+                figure.bake_into(&Transform::translate(offset)).unwrap_or_else(|| {
+                    Figures::Translate(Translate {
+                        offset: offset,
+                        figure: Box::new(figure),
+                    })
+                })
+            },
+            Figures::RotateY(r) => {
+                let rotation = Transform::new(Matrix3::new(
+                    V3(r.cos_theta, 0.0, r.sin_theta),
+                    V3(0.0, 1.0, 0.0),
+                    V3(-r.sin_theta, 0.0, r.cos_theta),
+                ), V3(0.0, 0.0, 0.0));
+                let (sin_theta, cos_theta, bbox) = (r.sin_theta, r.cos_theta, r.bbox);
+                let figure = r.figure.bake_transform();
+                figure.bake_into(&rotation).unwrap_or_else(|| {
+                    Figures::RotateY(RotateY {
+                        sin_theta: sin_theta,
+                        cos_theta: cos_theta,
+                        figure: Box::new(figure),
+                        bbox: bbox,
+                    })
+                })
+            },
+            // a baked instance gets its own copy of the geometry; an unbakeable one keeps sharing it
+            Figures::Transformed(t) => {
+                match (*t.figure).clone().bake_transform().bake_into(&t.transform) {
+                    Some(figure) => figure,
+                    None => Figures::Transformed(t),
+                }
+            },
+            Figures::FlipNormals(f) => Figures::flip_normals(f.figure.bake_transform()),
+            Figures::Figures(fs) => Figures::Figures(fs.into_iter().map(|f| f.bake_transform()).collect()),
+            _ => self,
+        }
+    }
+
+    // the figure with the transform applied to its geometry, None when that isn't possible
+    fn bake_into(&self, transform: &Transform) -> Option<Figures> {
+        match (self, transform.axis_scale()) {
+            (Figures::Sphere(s), Some(scale)) if scale.x() == scale.y() && scale.y() == scale.z() => {
+                Some(Figures::sphere(transform.point(s.center), s.radius * scale.x()))
+            },
+            (Figures::XYRect(r), Some(_)) => {
+                let (p0, p1) = (transform.point(V3(r.x0, r.y0, r.k)), transform.point(V3(r.x1, r.y1, r.k)));
+                Some(Figures::xy_rect(p0.x(), p1.x(), p0.y(), p1.y(), p0.z()))
+            },
+            (Figures::YZRect(r), Some(_)) => {
+                let (p0, p1) = (transform.point(V3(r.k, r.y0, r.z0)), transform.point(V3(r.k, r.y1, r.z1)));
+                Some(Figures::yz_rect(p0.y(), p1.y(), p0.z(), p1.z(), p0.x()))
+            },
+            (Figures::XZRect(r), Some(_)) => {
+                let (p0, p1) = (transform.point(V3(r.x0, r.k, r.z0)), transform.point(V3(r.x1, r.k, r.z1)));
+                Some(Figures::xz_rect(p0.x(), p1.x(), p0.z(), p1.z(), p0.y()))
+            },
+            (Figures::Cuboid(c), Some(_)) => Some(Figures::cuboid(transform.point(c.pmin), transform.point(c.pmax))),
+            // a mirroring transform would flip the winding and with it the geometric normal
+            (Figures::Triangle(t), _) if transform.determinant() > 0.0 => {
+                Some(Figures::Triangle(Triangle {
+                    v0: transform.point(t.v0),
+                    v1: transform.point(t.v1),
+                    v2: transform.point(t.v2),
+                    normals: t.normals.map(|(n0, n1, n2)| (transform.normal(n0), transform.normal(n1), transform.normal(n2))),
+                    ..t.clone()
+                }))
+            },
+            (Figures::Quad(q), _) if transform.determinant() > 0.0 => {
+                Some(Figures::Quad(Quad {
+                    corner: transform.point(q.corner),
+                    u: transform.vector(q.u),
+                    v: transform.vector(q.v),
+                    ..q.clone()
+                }))
+            },
+            (Figures::FlipNormals(f), _) => f.figure.bake_into(transform).map(Figures::flip_normals),
+            (Figures::Figures(fs), _) => fs.iter().map(|f| f.bake_into(transform)).collect::<Option<Vec<Figures>>>().map(Figures::Figures),
+            _ => None,
+        }
+    }
+
     pub fn constant_medium(density: f32, boundary: Figures) -> Figures {
+        Figures::emissive_medium(density, V3(0.0, 0.0, 0.0), boundary)
+    }
+
+    // a medium that also glows, e.g. fire or plasma; pair it with a black isotropic material for a volume that
+    // only emits and absorbs
+    pub fn emissive_medium(density: f32, emission: V3, boundary: Figures) -> Figures {
         Figures::ConstantMedium(ConstantMedium {
             density: density,
+            emission: emission,
             boundary: Box::new(boundary),
         })
     }
 
     pub fn bvh_node(figures: Vec<Figures>, time0: f32, time1: f32) -> Figures {
-        Figures::BvhNode(BvhNode::new(figures, time0, time1))
+        Figures::bvh_node_with(figures, time0, time1, BvhSplit::ObjectMedian)
+    }
+
+    pub fn bvh_node_with(figures: Vec<Figures>, time0: f32, time1: f32, split: BvhSplit) -> Figures {
+        Figures::BvhNode(BvhNode::new(figures, time0, time1, split))
+    }
+
+    pub fn poisson_spheres(region: Aabb, min_dist: f32, radius_range: (f32, f32)) -> Figures {
+        Figures::poisson_spheres_seeded(region, min_dist, radius_range, 0)
+    }
+
+    // Bridson's poisson-disk sampling: centers are at least min_dist apart, and radii are capped at min_dist / 2
+    // so the spheres never overlap. the same seed always gives the same arrangement
+    pub fn poisson_spheres_seeded(region: Aabb, min_dist: f32, radius_range: (f32, f32), seed: u64) -> Figures {
+        const CANDIDATES: usize = 30;
+
+        let mut seed_bytes = [0u8; 16];
+        for i in 0..8 {
+            seed_bytes[i] = (seed >> (8 * i)) as u8;
+            seed_bytes[8 + i] = ((seed ^ 0x9e37_79b9_7f4a_7c15) >> (8 * i)) as u8;
+        }
+        let mut rng = XorShiftRng::from_seed(seed_bytes);
+
+        let (min, extent) = (region.min(), region.max() - region.min());
+        let cell = min_dist / 3f32.sqrt();
+        let dims = [
+            ((extent.x() / cell).ceil() as usize).max(1),
+            ((extent.y() / cell).ceil() as usize).max(1),
+            ((extent.z() / cell).ceil() as usize).max(1),
+        ];
+        let cell_of = |p: V3| -> [usize; 3] {
+            let d = p - min;
+            [
+                ((d.x() / cell) as usize).min(dims[0] - 1),
+                ((d.y() / cell) as usize).min(dims[1] - 1),
+                ((d.z() / cell) as usize).min(dims[2] - 1),
+            ]
+        };
+
+        // each cell is smaller than min_dist / sqrt(3), so it holds at most one point
+        let mut grid: Vec<Option<usize>> = vec![None; dims[0] * dims[1] * dims[2]];
+        let mut points: Vec<V3> = vec![];
+        let mut active = vec![];
+
+        let first = min + V3(rng.gen::<f32>() * extent.x(), rng.gen::<f32>() * extent.y(), rng.gen::<f32>() * extent.z());
+        let c = cell_of(first);
+        grid[(c[2] * dims[1] + c[1]) * dims[0] + c[0]] = Some(0);
+        points.push(first);
+        active.push(0);
+
+        while !active.is_empty() {
+            let slot = rng.gen_range(0, active.len());
+            let center = points[active[slot]];
+            let mut found = false;
+
+            for _ in 0..CANDIDATES {
+                // uniform direction, distance in [min_dist, 2 min_dist)
+                let direction = loop {
+                    let d = V3::in_unit_sphere_from(&mut rng);
+                    if d.square_norm() > 1e-6 {
+                        break d.normalize();
+                    }
+                };
+                let candidate = center + direction.scale(min_dist * (1.0 + rng.gen::<f32>()));
+                let inside = (0..3).all(|axis| {
+                    let (p, lo, hi) = (candidate.as_array()[axis], region.min().as_array()[axis], region.max().as_array()[axis]);
+                    lo <= p && p <= hi
+                });
+                if !inside {
+                    continue;
+                }
+
+                let c = cell_of(candidate);
+                let range = |i: usize, n: usize| i.saturating_sub(2)..(i + 3).min(n);
+                let mut clear = true;
+                'search: for z in range(c[2], dims[2]) {
+                    for y in range(c[1], dims[1]) {
+                        for x in range(c[0], dims[0]) {
+                            if let Some(other) = grid[(z * dims[1] + y) * dims[0] + x] {
+                                if (points[other] - candidate).square_norm() < min_dist * min_dist {
+                                    clear = false;
+                                    break 'search;
+                                }
+                            }
+                        }
+                    }
+                }
+
+                if clear {
+                    grid[(c[2] * dims[1] + c[1]) * dims[0] + c[0]] = Some(points.len());
+                    active.push(points.len());
+                    points.push(candidate);
+                    found = true;
+                    break;
+                }
+            }
+
+            if !found {
+                active.swap_remove(slot);
+            }
+        }
+
+        let max_radius = radius_range.1.min(0.5 * min_dist);
+        let min_radius = radius_range.0.min(max_radius);
+        let spheres = points.into_iter().map(|center| {
+            Figures::sphere(center, min_radius + rng.gen::<f32>() * (max_radius - min_radius))
+        }).collect();
+
+        Figures::bvh_node(spheres, 0.0, 1.0)
+    }
+
+    // an icosahedron whose faces are split in four `subdivisions` times, with the vertices pushed onto the sphere;
+    // smooth shaded with the exact sphere normals, so it should render close to Figures::sphere
+    pub fn tessellated_sphere(center: V3, radius: f32, subdivisions: u32) -> Figures {
+        let t = (1.0 + 5f32.sqrt()) / 2.0;
+        let mut vertices = vec![
+            V3(-1.0, t, 0.0), V3(1.0, t, 0.0), V3(-1.0, -t, 0.0), V3(1.0, -t, 0.0),
+            V3(0.0, -1.0, t), V3(0.0, 1.0, t), V3(0.0, -1.0, -t), V3(0.0, 1.0, -t),
+            V3(t, 0.0, -1.0), V3(t, 0.0, 1.0), V3(-t, 0.0, -1.0), V3(-t, 0.0, 1.0),
+        ].into_iter().map(|v| v.normalize()).collect::<Vec<V3>>();
+        let mut faces = vec![
+            [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+            [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+            [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+            [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+        ];
+
+        for _ in 0..subdivisions {
+            // edges shared by two faces get a single midpoint
+            let mut midpoints = HashMap::new();
+            let mut midpoint = |a: usize, b: usize, vertices: &mut Vec<V3>| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    vertices.push((vertices[a] + vertices[b]).normalize());
+                    vertices.len() - 1
+                })
+            };
+
+            faces = faces.iter().flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b, &mut vertices), midpoint(b, c, &mut vertices), midpoint(c, a, &mut vertices));
+                vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            }).collect();
+        }
+
+        let triangles = faces.iter().map(|&[a, b, c]| {
+            let (na, nb, nc) = (vertices[a], vertices[b], vertices[c]);
+            let triangle = Triangle::new(center + na.scale(radius), center + nb.scale(radius), center + nc.scale(radius))
+                .with_normals(na, nb, nc);
+            Figures::Triangle(triangle)
+        }).collect();
+
+        Figures::bvh_node(triangles, 0.0, 1.0)
+    }
+
+    // problems in the leaves that would render as black or NaN pixels instead of failing loudly
+    pub fn issues(&self) -> Vec<FigureIssue> {
+        let finite = |vs: &[V3]| vs.iter().all(|v| v.x().is_finite() && v.y().is_finite() && v.z().is_finite());
+        let rect = |a0: f32, a1: f32, b0: f32, b1: f32, k: f32| {
+            if !(a0.is_finite() && a1.is_finite() && b0.is_finite() && b1.is_finite() && k.is_finite()) {
+                vec![FigureIssue::NonFiniteCoordinate]
+            } else if a0 >= a1 || b0 >= b1 {
+                vec![FigureIssue::DegenerateRect]
+            } else {
+                vec![]
+            }
+        };
+
+        match self {
+            Figures::Sphere(f) => {
+                if !finite(&[f.center]) || !f.radius.is_finite() {
+                    vec![FigureIssue::NonFiniteCoordinate]
+                } else if f.radius.abs() <= 0.0 {
+                    vec![FigureIssue::ZeroRadiusSphere]
+                } else {
+                    vec![]
+                }
+            },
+            Figures::XYRect(f) => rect(f.x0, f.x1, f.y0, f.y1, f.k),
+            Figures::YZRect(f) => rect(f.y0, f.y1, f.z0, f.z1, f.k),
+            Figures::XZRect(f) => rect(f.x0, f.x1, f.z0, f.z1, f.k),
+            Figures::Triangle(f) => {
+                if !finite(&[f.v0, f.v1, f.v2]) {
+                    vec![FigureIssue::NonFiniteCoordinate]
+                } else if (f.v1 - f.v0).cross(f.v2 - f.v0).norm() <= 1e-12 {
+                    vec![FigureIssue::DegenerateTriangle]
+                } else {
+                    vec![]
+                }
+            },
+            Figures::Quad(f) => {
+                if !finite(&[f.corner, f.u, f.v]) {
+                    vec![FigureIssue::NonFiniteCoordinate]
+                } else if f.area() <= 1e-12 {
+                    vec![FigureIssue::DegenerateRect]
+                } else {
+                    vec![]
+                }
+            },
+            Figures::Plane(f) => {
+                if !finite(&[f.point, f.normal]) {
+                    vec![FigureIssue::NonFiniteCoordinate]
+                } else {
+                    vec![]
+                }
+            },
+            Figures::FlipNormals(f) => f.figure.issues(),
+            Figures::Cuboid(f) => f.figure.issues(),
+            Figures::Translate(f) => f.figure.issues(),
+            Figures::RotateY(f) => f.figure.issues(),
+            Figures::RotateYMotion(f) => f.figure.issues(),
+            Figures::Transformed(f) => f.figure.issues(),
+            Figures::ConstantMedium(f) => {
+                let mut issues = f.boundary.issues();
+                if !(f.density > 0.0) {
+                    issues.push(FigureIssue::NonPositiveDensity);
+                }
+                issues
+            },
+            Figures::Figures(fs) => fs.iter().flat_map(|f| f.issues()).collect(),
+            Figures::BvhNode(f) => {
+                let mut issues = f.left.issues();
+                issues.extend(f.right.issues());
+                issues
+            },
+            Figures::Custom(_) => vec![],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Figures::Figures(fs) => fs.iter().all(|f| f.is_empty()),
+            _ => false,
+        }
+    }
+
+    // the nearest point on the surface, for the analytic primitives and planes and translations of them
+    pub fn closest_point(&self, p: V3) -> Option<V3> {
+        let clamp = |x: f32, a0: f32, a1: f32| x.max(a0).min(a1);
+
+        match self {
+            Figures::Sphere(f) => {
+                let d = p - f.center;
+                // every point of the surface is equally near the center, so any will do
+                let direction = if d.norm() > 0.0 { d.normalize() } else { V3(1.0, 0.0, 0.0) };
+                Some(f.center + direction.scale(f.radius.abs()))
+            },
+            Figures::XYRect(f) => Some(V3(clamp(p.x(), f.x0, f.x1), clamp(p.y(), f.y0, f.y1), f.k)),
+            Figures::YZRect(f) => Some(V3(f.k, clamp(p.y(), f.y0, f.y1), clamp(p.z(), f.z0, f.z1))),
+            Figures::XZRect(f) => Some(V3(clamp(p.x(), f.x0, f.x1), f.k, clamp(p.z(), f.z0, f.z1))),
+            Figures::Cuboid(f) => {
+                let (pmin, pmax) = (f.pmin, f.pmax);
+                let inside = V3(clamp(p.x(), pmin.x(), pmax.x()), clamp(p.y(), pmin.y(), pmax.y()), clamp(p.z(), pmin.z(), pmax.z()));
+                if inside.x() != p.x() || inside.y() != p.y() || inside.z() != p.z() {
+                    return Some(inside);
+                }
+
+                // from inside the box the nearest point lies on the nearest face
+                let faces = [
+                    (p.x() - pmin.x(), V3(pmin.x(), p.y(), p.z())),
+                    (pmax.x() - p.x(), V3(pmax.x(), p.y(), p.z())),
+                    (p.y() - pmin.y(), V3(p.x(), pmin.y(), p.z())),
+                    (pmax.y() - p.y(), V3(p.x(), pmax.y(), p.z())),
+                    (p.z() - pmin.z(), V3(p.x(), p.y(), pmin.z())),
+                    (pmax.z() - p.z(), V3(p.x(), p.y(), pmax.z())),
+                ];
+                faces.iter().min_by(|a, b| a.0.partial_cmp(&b.0).unwrap()).map(|&(_, q)| q)
+            },
+            Figures::Plane(f) => Some(p - f.normal.scale((p - f.point).dot(f.normal))),
+            Figures::FlipNormals(f) => f.figure.closest_point(p),
+            Figures::Translate(f) => f.figure.closest_point(p - f.offset).map(|q| q + f.offset),
+            _ => None,
+        }
     }
 
     pub fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
@@ -726,12 +1840,18 @@ impl Figures {
             Figures::XYRect(f) => f.hit(ray, tmin, tmax),
             Figures::YZRect(f) => f.hit(ray, tmin, tmax),
             Figures::XZRect(f) => f.hit(ray, tmin, tmax),
+            Figures::Triangle(f) => f.hit(ray, tmin, tmax),
+            Figures::Quad(f) => f.hit(ray, tmin, tmax),
+            Figures::Plane(f) => f.hit(ray, tmin, tmax),
             Figures::FlipNormals(f) => f.hit(ray, tmin, tmax),
             Figures::Cuboid(f) => f.hit(ray, tmin, tmax),
             Figures::Translate(f) => f.hit(ray, tmin, tmax),
             Figures::RotateY(f) => f.hit(ray, tmin, tmax),
+            Figures::RotateYMotion(f) => f.hit(ray, tmin, tmax),
+            Figures::Transformed(f) => f.hit(ray, tmin, tmax),
             Figures::ConstantMedium(f) => f.hit(ray, tmin, tmax),
             Figures::BvhNode(f) => f.hit(ray, tmin, tmax),
+            Figures::Custom(f) => f.hit(ray, tmin, tmax),
             Figures::Figures(fs) => {
                 let mut closest_parameter = tmax;
                 let mut record = None;
@@ -754,13 +1874,30 @@ impl Figures {
             Figures::XYRect(f) => f.bounding_box(tmin, tmax),
             Figures::YZRect(f) => f.bounding_box(tmin, tmax),
             Figures::XZRect(f) => f.bounding_box(tmin, tmax),
+            Figures::Triangle(f) => f.bounding_box(tmin, tmax),
+            Figures::Quad(f) => f.bounding_box(tmin, tmax),
+            Figures::Plane(f) => f.bounding_box(tmin, tmax),
             Figures::FlipNormals(f) => f.bounding_box(tmin, tmax),
             Figures::Cuboid(f) => f.bounding_box(tmin, tmax),
             Figures::Translate(f) => f.bounding_box(tmin, tmax),
             Figures::RotateY(f) => f.bounding_box(tmin, tmax),
+            Figures::RotateYMotion(f) => f.bounding_box(tmin, tmax),
+            Figures::Transformed(f) => f.bounding_box(tmin, tmax),
             Figures::ConstantMedium(f) => f.bounding_box(tmin, tmax),
             Figures::BvhNode(f) => f.bounding_box(tmin, tmax),
-            Figures::Figures(fs) => unimplemented!(),
+            Figures::Custom(f) => f.bounding_box(tmin, tmax),
+            Figures::Figures(fs) => {
+                let mut bbox: Option<Aabb> = None;
+                for f in fs {
+                    let b = f.bounding_box(tmin, tmax)?;
+                    bbox = Some(match bbox {
+                        Some(acc) => acc.surround(&b),
+                        None => b,
+                    });
+                }
+
+                bbox
+            },
         }
     }
 
@@ -770,12 +1907,18 @@ impl Figures {
             Figures::XYRect(f) => f.pdf_value(o, v),
             Figures::YZRect(f) => f.pdf_value(o, v),
             Figures::XZRect(f) => f.pdf_value(o, v),
+            Figures::Triangle(f) => f.pdf_value(o, v),
+            Figures::Quad(f) => f.pdf_value(o, v),
+            Figures::Plane(f) => f.pdf_value(o, v),
             Figures::FlipNormals(f) => f.pdf_value(o, v),
             Figures::Cuboid(f) => f.pdf_value(o, v),
             Figures::Translate(f) => f.pdf_value(o, v),
             Figures::RotateY(f) => f.pdf_value(o, v),
+            Figures::RotateYMotion(f) => f.pdf_value(o, v),
+            Figures::Transformed(f) => f.pdf_value(o, v),
             Figures::ConstantMedium(f) => f.pdf_value(o, v),
             Figures::BvhNode(f) => f.pdf_value(o, v),
+            Figures::Custom(f) => f.pdf_value(o, v),
             Figures::Figures(fs) => {
                 let weight = 1.0 / fs.len() as f32;
                 fs.iter().map(|object| {
@@ -791,16 +1934,306 @@ impl Figures {
             Figures::XYRect(f) => f.random(o),
             Figures::YZRect(f) => f.random(o),
             Figures::XZRect(f) => f.random(o),
+            Figures::Triangle(f) => f.random(o),
+            Figures::Quad(f) => f.random(o),
+            Figures::Plane(f) => f.random(o),
             Figures::FlipNormals(f) => f.random(o),
             Figures::Cuboid(f) => f.random(o),
             Figures::Translate(f) => f.random(o),
             Figures::RotateY(f) => f.random(o),
+            Figures::RotateYMotion(f) => f.random(o),
+            Figures::Transformed(f) => f.random(o),
             Figures::ConstantMedium(f) => f.random(o),
             Figures::BvhNode(f) => f.random(o),
+            Figures::Custom(f) => f.random(o),
+            Figures::Figures(fs) if fs.is_empty() => V3(1.0, 0.0, 0.0),
             Figures::Figures(fs) => {
-                let index = (rand::random::<f32>() * fs.len() as f32) as usize;
+                let index = (random() * fs.len() as f32) as usize;
                 fs[index].random(o)
             },
         }
     }
+
+    pub fn random_from(&self, o: V3, r: (f32, f32)) -> V3 {
+        match self {
+            Figures::Sphere(f) => f.random_from(o, r),
+            Figures::XYRect(f) => f.random_from(o, r),
+            Figures::YZRect(f) => f.random_from(o, r),
+            Figures::XZRect(f) => f.random_from(o, r),
+            Figures::Triangle(f) => f.random_from(o, r),
+            Figures::Quad(f) => f.random_from(o, r),
+            Figures::Plane(f) => f.random_from(o, r),
+            Figures::FlipNormals(f) => f.random_from(o, r),
+            Figures::Cuboid(f) => f.random_from(o, r),
+            Figures::Translate(f) => f.random_from(o, r),
+            Figures::RotateY(f) => f.random_from(o, r),
+            Figures::RotateYMotion(f) => f.random_from(o, r),
+            Figures::Transformed(f) => f.random_from(o, r),
+            Figures::ConstantMedium(f) => f.random_from(o, r),
+            Figures::BvhNode(f) => f.random_from(o, r),
+            Figures::Custom(f) => f.random_from(o, r),
+            Figures::Figures(fs) if fs.is_empty() => V3(1.0, 0.0, 0.0),
+            Figures::Figures(fs) => {
+                let scaled = r.0 * fs.len() as f32;
+                let index = (scaled as usize).min(fs.len() - 1);
+                fs[index].random_from(o, (scaled - index as f32, r.1))
+            },
+        }
+    }
+}
+
+/// Translate, rotate and scale anything that can become a `Figures`.
+///
+/// Every `Hit` implementor gets this through a blanket impl, so a downstream primitive is placed in a scene with
+/// e.g. `MyShape::new().rotated_y(30.0).translated(offset)`, without naming `Figures::custom`.
+pub trait Transformable: Sized {
+    fn into_figure(self) -> Figures;
+
+    fn translated(self, offset: V3) -> Figures {
+        Figures::translate(offset, self.into_figure())
+    }
+
+    fn rotated_y(self, angle: f32) -> Figures {
+        Figures::rotate_y(angle, self.into_figure())
+    }
+
+    fn scaled(self, factors: V3) -> Figures {
+        Figures::scale(factors, self.into_figure())
+    }
+
+    fn transformed(self, transform: Transform) -> Figures {
+        Figures::transform(transform, self.into_figure())
+    }
+}
+
+impl Transformable for Figures {
+    fn into_figure(self) -> Figures {
+        self
+    }
+}
+
+impl<H: Hit + 'static> Transformable for H {
+    fn into_figure(self) -> Figures {
+        Figures::custom(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ray(origin: V3, direction: V3) -> Ray {
+        Ray { origin: origin, direction: V3U::new(direction), time: 0.0, wavelength: None }
+    }
+
+    #[test]
+    fn baked_translation_hits_like_the_wrapper() {
+        let wrapped = Figures::translate(V3(3.0, -1.0, 2.0), Figures::sphere(V3(1.0, 0.0, 0.0), 0.5));
+        let baked = wrapped.clone().bake_transform();
+        match &baked {
+            Figures::Sphere(_) => {},
+            _ => panic!("translated sphere was not baked"),
+        }
+
+        let mut rng = seeded_rng(6);
+        for _ in 0..500 {
+            let origin = V3(4.0, -1.0, 2.0) + V3::in_unit_sphere_from(&mut rng).scale(3.0);
+            let r = ray(origin, V3::in_unit_sphere_from(&mut rng));
+            match (wrapped.hit(&r, 0.001, std::f32::MAX), baked.hit(&r, 0.001, std::f32::MAX)) {
+                (Some(a), Some(b)) => {
+                    assert!((a.at - b.at).abs() < 1e-4);
+                    assert!((a.point - b.point).norm() < 1e-4 && (a.normal - b.normal).norm() < 1e-4);
+                    assert!(a.front_face == b.front_face);
+                },
+                (None, None) => {},
+                _ => panic!("baked and wrapped sphere disagree"),
+            }
+        }
+    }
+
+    fn unit_box(min: V3) -> Aabb {
+        Aabb::new(min, min + V3(1.0, 1.0, 1.0))
+    }
+
+    #[test]
+    fn disjoint_boxes_dont_overlap() {
+        let (a, b) = (unit_box(V3(0.0, 0.0, 0.0)), unit_box(V3(0.0, 1.5, 0.0)));
+        assert!(!a.overlaps(&b) && !b.overlaps(&a));
+        assert!(a.intersection(&b).is_none());
+    }
+
+    #[test]
+    fn touching_boxes_intersect_in_a_face() {
+        let (a, b) = (unit_box(V3(0.0, 0.0, 0.0)), unit_box(V3(1.0, 0.5, 0.0)));
+        assert!(a.overlaps(&b) && b.overlaps(&a));
+        let face = a.intersection(&b).unwrap();
+        assert!(face.min().as_array() == [1.0, 0.5, 0.0]);
+        assert!(face.max().as_array() == [1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn nested_box_is_its_own_intersection() {
+        let outer = Aabb::new(V3(-2.0, -2.0, -2.0), V3(2.0, 2.0, 2.0));
+        let inner = unit_box(V3(0.0, -1.0, 0.5));
+        assert!(outer.overlaps(&inner) && inner.overlaps(&outer));
+        for intersection in [outer.intersection(&inner).unwrap(), inner.intersection(&outer).unwrap()].iter() {
+            assert!(intersection.min().as_array() == inner.min().as_array());
+            assert!(intersection.max().as_array() == inner.max().as_array());
+        }
+    }
+
+    fn assert_near(a: V3, b: [f32; 3]) {
+        assert!((a - V3::from(b)).norm() < 1e-5, "{:?} != {:?}", a.as_array(), b);
+    }
+
+    #[test]
+    fn closest_point_on_sphere() {
+        let sphere = Figures::sphere(V3(1.0, 0.0, 0.0), 2.0);
+        assert_near(sphere.closest_point(V3(1.0, 5.0, 0.0)).unwrap(), [1.0, 2.0, 0.0]);
+        assert_near(sphere.closest_point(V3(1.5, 0.0, 0.0)).unwrap(), [3.0, 0.0, 0.0]);
+        assert_near(Figures::translate(V3(0.0, 0.0, 10.0), sphere).closest_point(V3(1.0, 0.0, 0.0)).unwrap(), [1.0, 0.0, 8.0]);
+    }
+
+    #[test]
+    fn closest_point_on_box() {
+        let cuboid = Figures::cuboid(V3(0.0, 0.0, 0.0), V3(2.0, 4.0, 6.0));
+        // outside: clamped onto the box, at a face, an edge and a corner
+        assert_near(cuboid.closest_point(V3(1.0, 2.0, -3.0)).unwrap(), [1.0, 2.0, 0.0]);
+        assert_near(cuboid.closest_point(V3(5.0, 2.0, -3.0)).unwrap(), [2.0, 2.0, 0.0]);
+        assert_near(cuboid.closest_point(V3(-1.0, 9.0, 9.0)).unwrap(), [0.0, 4.0, 6.0]);
+        // inside: pushed out through the nearest face
+        assert_near(cuboid.closest_point(V3(1.0, 3.5, 3.0)).unwrap(), [1.0, 4.0, 3.0]);
+    }
+
+    fn timed_ray(origin: V3, direction: V3, time: f32) -> Ray {
+        Ray { origin: origin, direction: V3U::new(direction), time: time, wavelength: None }
+    }
+
+    fn spinning_box() -> Figures {
+        Figures::cuboid(V3(0.5, -0.5, -0.25), V3(2.0, 0.5, 0.25))
+    }
+
+    #[test]
+    fn rotate_y_motion_follows_ray_time() {
+        let spinning = Figures::rotate_y_motion(0.0, 90.0, spinning_box());
+        let mut rng = seeded_rng(8);
+        for &time in [0.0, 0.25, 0.5, 1.0].iter() {
+            let still = Figures::rotate_y(90.0 * time, spinning_box());
+            for _ in 0..200 {
+                let r = timed_ray(V3::in_unit_sphere_from(&mut rng).scale(5.0), V3::in_unit_sphere_from(&mut rng), time);
+                match (spinning.hit(&r, 0.001, std::f32::MAX), still.hit(&r, 0.001, std::f32::MAX)) {
+                    (Some(a), Some(b)) => assert!((a.at - b.at).abs() < 1e-4 && (a.normal - b.normal).norm() < 1e-4),
+                    (None, None) => {},
+                    _ => panic!("spinning box at time {} differs from the still one", time),
+                }
+            }
+        }
+
+        // the arm points along +x at time 0 and along -z at time 1
+        let along_x = timed_ray(V3(1.5, 0.0, 5.0), V3(0.0, 0.0, -1.0), 0.0);
+        assert!(spinning.hit(&along_x, 0.001, std::f32::MAX).is_some());
+        assert!(spinning.hit(&timed_ray(along_x.origin, V3(0.0, 0.0, -1.0), 1.0), 0.001, std::f32::MAX).is_none());
+    }
+
+    #[test]
+    fn rotate_y_motion_box_covers_the_sweep() {
+        let bbox = Figures::rotate_y_motion(-30.0, 200.0, spinning_box()).bounding_box(0.0, 1.0).unwrap();
+        for i in 0..=100 {
+            let angle = -30.0 + 230.0 * i as f32 / 100.0;
+            let still = Figures::rotate_y(angle, spinning_box()).bounding_box(0.0, 1.0).unwrap();
+            for axis in 0..3 {
+                assert!(bbox.min().as_array()[axis] <= still.min().as_array()[axis] + 1e-4);
+                assert!(bbox.max().as_array()[axis] >= still.max().as_array()[axis] - 1e-4);
+            }
+        }
+
+        // no larger than the circle the far corners sweep
+        let reach = (2.0f32 * 2.0 + 0.25 * 0.25).sqrt();
+        assert!(bbox.min().as_array().iter().chain(bbox.max().as_array().iter()).all(|c| c.abs() <= reach + 1e-4));
+    }
+
+    #[test]
+    fn quad_hits_like_the_matching_rect() {
+        let rect = Figures::xz_rect(-1.0, 2.0, 0.0, 1.0, 3.0);
+        // rects turn their normal towards the ray, as a double-sided quad does
+        let quad = Figures::quad_double_sided(V3(-1.0, 3.0, 0.0), V3(0.0, 0.0, 1.0), V3(3.0, 0.0, 0.0));
+        let mut rng = seeded_rng(10);
+        for _ in 0..500 {
+            let r = ray(V3::in_unit_sphere_from(&mut rng).scale(4.0), V3::in_unit_sphere_from(&mut rng));
+            match (rect.hit(&r, 0.001, std::f32::MAX), quad.hit(&r, 0.001, std::f32::MAX)) {
+                (Some(a), Some(b)) => {
+                    assert!((a.at - b.at).abs() < 1e-4 && a.front_face == b.front_face);
+                    assert!((a.normal - b.normal).norm() < 1e-5);
+                    // u runs along the quad's first edge, which is the rect's z
+                    assert!((a.v - b.u).abs() < 1e-4 && (a.u - b.v).abs() < 1e-4);
+                },
+                (None, None) => {},
+                _ => panic!("quad and rect disagree"),
+            }
+        }
+
+        let o = V3(0.0, 0.0, 0.5);
+        for &r in [(0.2, 0.7), (0.9, 0.1)].iter() {
+            let direction = V3U::new(quad.random_from(o, r));
+            assert!((rect.pdf_value(o, direction) - quad.pdf_value(o, direction)).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn double_sided_quad_faces_the_ray() {
+        let quad = Figures::quad_double_sided(V3(-1.0, -1.0, 0.0), V3(2.0, 0.0, 0.0), V3(1.0, 2.0, 0.0));
+        for &(origin, direction) in [(V3(0.0, 0.0, 5.0), V3(0.0, 0.0, -1.0)), (V3(0.0, 0.0, -5.0), V3(0.0, 0.0, 1.0))].iter() {
+            let rec = quad.hit(&ray(origin, direction), 0.001, std::f32::MAX).unwrap();
+            assert!(rec.normal.dot(direction) < 0.0);
+        }
+
+        let one_sided = Figures::quad(V3(-1.0, -1.0, 0.0), V3(2.0, 0.0, 0.0), V3(1.0, 2.0, 0.0));
+        let rec = one_sided.hit(&ray(V3(0.0, 0.0, -5.0), V3(0.0, 0.0, 1.0)), 0.001, std::f32::MAX).unwrap();
+        assert!(!rec.front_face && rec.normal.z() > 0.0);
+    }
+
+    #[test]
+    fn rect_normals_face_the_ray() {
+        let floor = Figures::xz_rect(-1.0, 1.0, -1.0, 1.0, 0.0);
+        let flipped = Figures::flip_normals(floor.clone());
+        for &(origin, from_above) in [(V3(0.0, 2.0, 0.0), true), (V3(0.0, -2.0, 0.0), false)].iter() {
+            let r = ray(origin, -origin);
+            let rec = floor.hit(&r, 0.001, std::f32::MAX).unwrap();
+            assert!(rec.normal.dot(r.direction.as_V3()) < 0.0 && rec.front_face == from_above);
+
+            let rec = flipped.hit(&r, 0.001, std::f32::MAX).unwrap();
+            assert!(rec.normal.dot(r.direction.as_V3()) < 0.0 && rec.front_face != from_above);
+        }
+
+        // the walls of a box seen from inside, as the cornell box camera sees them
+        let room = Figures::cuboid(V3(-1.0, -1.0, -1.0), V3(1.0, 1.0, 1.0));
+        for &direction in [V3(1.0, 0.2, 0.1), V3(-0.3, -1.0, 0.2), V3(0.1, 0.4, -1.0)].iter() {
+            let r = ray(V3(0.0, 0.0, 0.0), direction);
+            let rec = room.hit(&r, 0.001, std::f32::MAX).unwrap();
+            assert!(rec.normal.dot(r.direction.as_V3()) < 0.0 && !rec.front_face);
+        }
+    }
+
+    #[test]
+    fn mirrored_instance_keeps_front_face() {
+        let mirror = Transform::scale(V3(-1.0, 1.0, 1.0)).then(&Transform::translate(V3(0.0, 0.0, 0.5)));
+        let sphere = Figures::instance(Arc::new(Figures::sphere(V3(1.0, 0.0, 0.0), 1.0)), mirror);
+        let outside = sphere.hit(&ray(V3(-1.0, 0.0, 5.0), V3(0.0, 0.0, -1.0)), 0.001, std::f32::MAX).unwrap();
+        assert!(outside.front_face && outside.normal.z() > 0.99);
+        let inside = sphere.hit(&ray(V3(-1.0, 0.0, 0.5), V3(0.0, 0.0, 1.0)), 0.001, std::f32::MAX).unwrap();
+        assert!(!inside.front_face);
+
+        // the quad's front is +z; the mirror reverses its winding but not which side is the front
+        let quad = Figures::instance(Arc::new(Figures::quad(V3(0.0, 0.0, 0.0), V3(1.0, 0.0, 0.0), V3(0.0, 1.0, 0.0))), mirror);
+        let front = quad.hit(&ray(V3(-0.5, 0.5, 5.0), V3(0.0, 0.0, -1.0)), 0.001, std::f32::MAX).unwrap();
+        assert!(front.front_face && front.normal.z() > 0.99);
+        let back = quad.hit(&ray(V3(-0.5, 0.5, -5.0), V3(0.0, 0.0, 1.0)), 0.001, std::f32::MAX).unwrap();
+        assert!(!back.front_face);
+    }
+
+    #[test]
+    fn figures_are_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Figures>();
+    }
 }
@@ -0,0 +1,148 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+use crate::vector::*;
+use crate::pdf::*;
+use crate::sampler::*;
+
+const THETA_BINS: usize = 8;
+const PHI_BINS: usize = 16;
+const REFRESH_INTERVAL: u32 = 256;
+
+#[derive(Clone)]
+struct GuideCell {
+    cdf: Arc<Vec<f32>>,
+    accum: Vec<f32>,
+    pending: u32,
+}
+
+impl GuideCell {
+    fn new() -> GuideCell {
+        let mut cell = GuideCell {
+            cdf: Arc::new(vec![]),
+            accum: vec![0.0; THETA_BINS * PHI_BINS],
+            pending: 0,
+        };
+        cell.refresh();
+        cell
+    }
+
+    fn refresh(&mut self) {
+        let n = self.accum.len() as f32;
+        let total: f32 = self.accum.iter().sum();
+
+        // keep a uniform floor so that no direction ever gets zero probability
+        let prior = if total > 0.0 { 0.1 * total / n } else { 1.0 };
+
+        let mut acc = 0.0;
+        let mut cdf = self.accum.iter().map(|w| {
+            acc += w + prior;
+            acc
+        }).collect::<Vec<f32>>();
+        for c in cdf.iter_mut() {
+            *c /= acc;
+        }
+
+        self.cdf = Arc::new(cdf);
+        self.pending = 0;
+    }
+}
+
+#[derive(Clone)]
+pub struct PathGuide {
+    min: V3,
+    max: V3,
+    resolution: usize,
+    cells: RefCell<Vec<GuideCell>>,
+}
+
+impl PathGuide {
+    pub fn new(min: V3, max: V3, resolution: usize) -> PathGuide {
+        let resolution = resolution.max(1);
+
+        PathGuide {
+            min: min,
+            max: max,
+            resolution: resolution,
+            cells: RefCell::new(vec![GuideCell::new(); resolution * resolution * resolution]),
+        }
+    }
+
+    fn cell_index(&self, point: &V3) -> usize {
+        let n = self.resolution;
+        let axis = |p: f32, lo: f32, hi: f32| {
+            let t = (p - lo) / (hi - lo);
+            if t.is_nan() { 0 } else { ((t * n as f32) as usize).min(n - 1) }
+        };
+
+        let i = axis(point.x(), self.min.x(), self.max.x());
+        let j = axis(point.y(), self.min.y(), self.max.y());
+        let k = axis(point.z(), self.min.z(), self.max.z());
+        (i * n + j) * n + k
+    }
+
+    fn bin_index(direction: &V3U) -> usize {
+        let t = (((direction.z() + 1.0) * 0.5 * THETA_BINS as f32) as usize).min(THETA_BINS - 1);
+        let phi = direction.y().atan2(direction.x()) + std::f32::consts::PI;
+        let p = ((phi / (2.0 * std::f32::consts::PI) * PHI_BINS as f32) as usize).min(PHI_BINS - 1);
+        t * PHI_BINS + p
+    }
+
+    fn bin_direction(bin: usize) -> V3 {
+        let t = bin / PHI_BINS;
+        let p = bin % PHI_BINS;
+        let z = -1.0 + 2.0 * (t as f32 + random()) / THETA_BINS as f32;
+        let phi = 2.0 * std::f32::consts::PI * (p as f32 + random()) / PHI_BINS as f32 - std::f32::consts::PI;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        V3(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    fn bin_solid_angle() -> f32 {
+        4.0 * std::f32::consts::PI / (THETA_BINS * PHI_BINS) as f32
+    }
+
+    pub fn pdf(&self, point: &V3) -> GuidePdf {
+        GuidePdf {
+            cdf: self.cells.borrow()[self.cell_index(point)].cdf.clone(),
+        }
+    }
+
+    pub fn record(&self, point: &V3, direction: &V3U, radiance: f32) {
+        if !radiance.is_finite() || radiance <= 0.0 {
+            return;
+        }
+
+        let index = self.cell_index(point);
+        let mut cells = self.cells.borrow_mut();
+        let cell = &mut cells[index];
+        cell.accum[PathGuide::bin_index(direction)] += radiance;
+        cell.pending += 1;
+        if cell.pending >= REFRESH_INTERVAL {
+            cell.refresh();
+        }
+    }
+
+    pub fn refresh(&self) {
+        for cell in self.cells.borrow_mut().iter_mut() {
+            cell.refresh();
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GuidePdf {
+    cdf: Arc<Vec<f32>>,
+}
+
+impl Pdf for GuidePdf {
+    fn value(&self, direction: &V3U) -> f32 {
+        let bin = PathGuide::bin_index(direction);
+        let prob = if bin == 0 { self.cdf[0] } else { self.cdf[bin] - self.cdf[bin - 1] };
+        prob / PathGuide::bin_solid_angle()
+    }
+
+    fn generate(&self) -> V3 {
+        let r = random();
+        let bin = self.cdf.iter().position(|&c| r < c).unwrap_or(self.cdf.len() - 1);
+        PathGuide::bin_direction(bin)
+    }
+}
@@ -0,0 +1,176 @@
+use std::fs;
+use std::io;
+use crate::vector::*;
+
+#[derive(Clone)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<V3>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<V3>) -> Image {
+        Image {
+            width: width,
+            height: height,
+            pixels: pixels,
+        }
+    }
+
+    // picks the decoder from the magic bytes, falling back to the extension; every format ends up linear:
+    // 8/16-bit png and jpeg go through the inverse sRGB curve while hdr is kept as is
+    pub fn load(file_name: &str) -> io::Result<Image> {
+        let bytes = fs::read(file_name)?;
+        if bytes.starts_with(b"P3") || bytes.starts_with(b"P6") {
+            return Image::load_ppm(file_name);
+        }
+
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", file_name, message));
+        let format = ::image::guess_format(&bytes)
+            .or_else(|_| ::image::ImageFormat::from_path(file_name))
+            .map_err(|e| invalid(e.to_string()))?;
+        let decoded = ::image::load_from_memory_with_format(&bytes, format).map_err(|e| invalid(e.to_string()))?;
+
+        let is_linear = match decoded {
+            ::image::DynamicImage::ImageRgb32F(_) | ::image::DynamicImage::ImageRgba32F(_) => true,
+            _ => false,
+        };
+        let rgb = decoded.to_rgb32f();
+        let pixels = rgb.pixels().map(|p| {
+            let c = V3(p[0], p[1], p[2]);
+            if is_linear { c } else { c.map(&Image::srgb_to_linear) }
+        }).collect();
+
+        Ok(Image::new(rgb.width() as usize, rgb.height() as usize, pixels))
+    }
+
+    fn srgb_to_linear(x: f32) -> f32 {
+        if x <= 0.04045 { x / 12.92 } else { ((x + 0.055) / 1.055).powf(2.4) }
+    }
+
+    // reads binary (P6, 8 or 16 bits per sample) or ascii (P3) ppm; values are decoded with the same gamma 2 we encode with
+    pub fn load_ppm(file_name: &str) -> io::Result<Image> {
+        let bytes = fs::read(file_name)?;
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", file_name, message));
+
+        let mut pos = 0;
+        let mut header = vec![];
+        while header.len() < 4 {
+            while pos < bytes.len() && (bytes[pos] as char).is_whitespace() {
+                pos += 1;
+            }
+            if pos < bytes.len() && bytes[pos] == b'#' {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+                continue;
+            }
+
+            let start = pos;
+            while pos < bytes.len() && !(bytes[pos] as char).is_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(invalid("truncated header"));
+            }
+            header.push(String::from_utf8_lossy(&bytes[start..pos]).to_string());
+        }
+
+        let parse = |s: &str| s.parse::<usize>().map_err(|_| invalid("malformed header"));
+        let width = parse(&header[1])?;
+        let height = parse(&header[2])?;
+        let max_value = parse(&header[3])?;
+        if max_value == 0 || max_value > 65535 {
+            return Err(invalid("unsupported max value"));
+        }
+
+        let values: Vec<f32> = match header[0].as_str() {
+            // samples are one byte up to a max value of 255 and two big-endian bytes above
+            "P6" if max_value > 255 => {
+                let data = &bytes[(pos + 1).min(bytes.len())..];
+                data.chunks_exact(2).map(|b| u16::from_be_bytes([b[0], b[1]]) as f32).collect()
+            },
+            "P6" => {
+                let data = &bytes[(pos + 1).min(bytes.len())..];
+                data.iter().map(|b| *b as f32).collect()
+            },
+            "P3" => {
+                String::from_utf8_lossy(&bytes[pos..])
+                    .split_whitespace()
+                    .map(|t| t.parse::<f32>().map_err(|_| invalid("malformed pixel")))
+                    .collect::<io::Result<Vec<f32>>>()?
+            },
+            _ => return Err(invalid("unsupported format")),
+        };
+        if values.len() < width * height * 3 {
+            return Err(invalid("truncated pixel data"));
+        }
+
+        let max_value = max_value as f32;
+        let pixels = values.chunks(3).take(width * height).map(|c| {
+            V3(c[0] / max_value, c[1] / max_value, c[2] / max_value).map(&|x| x * x)
+        }).collect();
+
+        Ok(Image::new(width, height, pixels))
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> V3 {
+        self.pixels[y.min(self.height - 1) * self.width + x.min(self.width - 1)]
+    }
+
+    // bilinear lookup with (0,0) at the top-left corner; coordinates are clamped to the edge texels
+    pub fn sample(&self, u: f32, v: f32) -> V3 {
+        let x = (u * self.width as f32 - 0.5).max(0.0).min((self.width - 1) as f32);
+        let y = (v * self.height as f32 - 0.5).max(0.0).min((self.height - 1) as f32);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+
+        let top = self.pixel(x0, y0).scale(1.0 - tx) + self.pixel(x0 + 1, y0).scale(tx);
+        let bottom = self.pixel(x0, y0 + 1).scale(1.0 - tx) + self.pixel(x0 + 1, y0 + 1).scale(tx);
+        top.scale(1.0 - ty) + bottom.scale(ty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("ruyt-test-{}", name)).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn png_is_linearized() {
+        let path = temp_path("load.png");
+        let mut png = ::image::RgbImage::new(2, 1);
+        png.put_pixel(1, 0, ::image::Rgb([128, 255, 0]));
+        png.save(&path).unwrap();
+
+        let image = Image::load(&path).unwrap();
+        let [r, g, b] = image.pixel(1, 0).as_array();
+        assert!((r - 0.215_861).abs() < 1e-4);
+        assert!((g - 1.0).abs() < 1e-6 && b == 0.0);
+    }
+
+    #[test]
+    fn reads_16_bit_ppm() {
+        let path = temp_path("load16.ppm");
+        let mut bytes = b"P6\n1 1\n65535\n".to_vec();
+        bytes.extend_from_slice(&[0x80, 0x00, 0xff, 0xff, 0x00, 0x00]);
+        fs::write(&path, bytes).unwrap();
+
+        let [r, g, b] = Image::load(&path).unwrap().pixel(0, 0).as_array();
+        let half = 32768.0 / 65535.0;
+        assert!((r - half * half).abs() < 1e-6);
+        assert!((g - 1.0).abs() < 1e-6 && b == 0.0);
+    }
+}
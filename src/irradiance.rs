@@ -0,0 +1,103 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use crate::vector::*;
+
+const MIN_SAMPLES: u32 = 16;
+
+#[derive(Clone)]
+struct CacheEntry {
+    sum: V3,
+    luminance_sq: f32,
+    count: u32,
+}
+
+impl CacheEntry {
+    fn new() -> CacheEntry {
+        CacheEntry {
+            sum: V3(0.0, 0.0, 0.0),
+            luminance_sq: 0.0,
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, other: &CacheEntry) {
+        self.sum = self.sum + other.sum;
+        self.luminance_sq += other.luminance_sq;
+        self.count += other.count;
+    }
+
+    // relative standard error of the mean luminance
+    fn error(&self) -> f32 {
+        let n = self.count as f32;
+        let mean = (self.sum.x() + self.sum.y() + self.sum.z()) / (3.0 * n);
+        let variance = (self.luminance_sq / n - mean * mean).max(0.0);
+        if mean > 0.0 { (variance / n).sqrt() / mean } else { std::f32::MAX }
+    }
+}
+
+// caches the cosine-weighted incoming radiance of diffuse hits on a grid keyed by position and the dominant
+// axis of the normal; an entry is only used once its estimate is within max_error, which trades bias for speed
+#[derive(Clone)]
+pub struct IrradianceCache {
+    spacing: f32,
+    max_error: f32,
+    entries: RefCell<HashMap<(i32, i32, i32, u8), CacheEntry>>,
+    pending: RefCell<HashMap<(i32, i32, i32, u8), CacheEntry>>,
+}
+
+impl IrradianceCache {
+    pub fn new(spacing: f32, max_error: f32) -> IrradianceCache {
+        IrradianceCache {
+            spacing: spacing,
+            max_error: max_error,
+            entries: RefCell::new(HashMap::new()),
+            pending: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn key(&self, point: &V3, normal: &V3) -> (i32, i32, i32, u8) {
+        let cell = |p: f32| (p / self.spacing).floor() as i32;
+        let (ax, ay, az) = (normal.x().abs(), normal.y().abs(), normal.z().abs());
+        let side = if ax >= ay && ax >= az {
+            if normal.x() > 0.0 { 0 } else { 1 }
+        } else if ay >= az {
+            if normal.y() > 0.0 { 2 } else { 3 }
+        } else {
+            if normal.z() > 0.0 { 4 } else { 5 }
+        };
+
+        (cell(point.x()), cell(point.y()), cell(point.z()), side)
+    }
+
+    pub fn lookup(&self, point: &V3, normal: &V3) -> Option<V3> {
+        let entries = self.entries.borrow();
+        let entry = entries.get(&self.key(point, normal))?;
+        if entry.count >= MIN_SAMPLES && entry.error() <= self.max_error {
+            Some(entry.sum.scale(1.0 / entry.count as f32))
+        } else {
+            None
+        }
+    }
+
+    pub fn record(&self, point: &V3, normal: &V3, irradiance: V3) {
+        let luminance = (irradiance.x() + irradiance.y() + irradiance.z()) / 3.0;
+        if !luminance.is_finite() {
+            return;
+        }
+
+        let key = self.key(point, normal);
+        let mut pending = self.pending.borrow_mut();
+        let entry = pending.entry(key).or_insert_with(CacheEntry::new);
+        entry.sum = entry.sum + irradiance;
+        entry.luminance_sq += luminance * luminance;
+        entry.count += 1;
+    }
+
+    // folds this pass's samples into the entries lookups read from
+    pub fn refresh(&self) {
+        let mut entries = self.entries.borrow_mut();
+        for (key, sample) in self.pending.borrow_mut().drain() {
+            entries.entry(key).or_insert_with(CacheEntry::new).add(&sample);
+        }
+    }
+}
@@ -0,0 +1,2529 @@
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::time::{Duration, Instant};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use rand::Rng;
+
+mod vector;
+pub use crate::vector::*;
+
+mod figures;
+pub use crate::figures::*;
+
+mod textures;
+pub use crate::textures::*;
+
+mod pdf;
+pub use crate::pdf::*;
+
+mod materials;
+pub use crate::materials::*;
+
+mod guiding;
+pub use crate::guiding::*;
+
+mod spectrum;
+pub use crate::spectrum::*;
+
+mod sampler;
+pub use crate::sampler::*;
+
+mod image;
+pub use crate::image::*;
+
+mod background;
+pub use crate::background::*;
+
+mod obj;
+
+mod irradiance;
+pub use crate::irradiance::*;
+
+mod transform;
+pub use crate::transform::*;
+
+mod color;
+pub use crate::color::*;
+
+mod light_choices;
+pub use crate::light_choices::*;
+
+pub struct Objects {
+    pub figure: Figures,
+    pub material: Materials,
+}
+
+// shorthand for Scene::new with one `figure(args) => material(args);` line per object, naming the constructors of
+// Figures and Materials without their type:
+//
+//     scene! {
+//         sphere(V3(0.0, -1000.0, 0.0), 1000.0) => lambertian(solid(V3(0.5, 0.5, 0.5)));
+//         sphere(V3(0.0, 1.0, 0.0), 1.0) => metal(V3(0.7, 0.6, 0.5), 0.0);
+//     }
+//
+// a call in a material's first argument other than V3(..) is a Textures constructor; anything else is passed
+// through as an expression, so `lambertian(texture)` or `flip_normals(Figures::xz_rect(..))` work too
+#[macro_export]
+macro_rules! scene {
+    (@objects [$($done:expr),*]) => {
+        vec![$($done),*]
+    };
+    (@objects [$($done:expr),*] $figure:ident ($($figure_args:tt)*) => $material:ident ($($material_args:tt)*); $($rest:tt)*) => {
+        $crate::scene!(@objects [$($done,)* $crate::Objects {
+            figure: $crate::Figures::$figure($($figure_args)*),
+            material: $crate::scene!(@material $material ($($material_args)*)),
+        }] $($rest)*)
+    };
+    (@material $material:ident (V3 ($($v:tt)*) $(, $arg:expr)*)) => {
+        $crate::Materials::$material($crate::V3($($v)*) $(, $arg)*)
+    };
+    (@material $material:ident ($texture:ident ($($texture_args:tt)*) $(, $arg:expr)*)) => {
+        $crate::Materials::$material($crate::Textures::$texture($($texture_args)*) $(, $arg)*)
+    };
+    (@material $material:ident ($($arg:expr),*)) => {
+        $crate::Materials::$material($($arg),*)
+    };
+    ($($objects:tt)*) => {
+        $crate::Scene::new($crate::scene!(@objects [] $($objects)*))
+    };
+}
+
+pub struct Color(pub u8, pub u8, pub u8);
+
+impl Color {
+    pub fn red(&self) -> u8 {
+        self.0
+    }
+
+    pub fn green(&self) -> u8 {
+        self.1
+    }
+
+    pub fn blue(&self) -> u8 {
+        self.2
+    }
+
+    pub fn from_f32(r: f32, g: f32, b: f32) -> Color {
+        Color(
+            (r.min(1.0) * 255.99) as u8,
+            (g.min(1.0) * 255.99) as u8,
+            (b.min(1.0) * 255.99) as u8,
+        )
+    }
+
+    pub fn from_v3(v: V3) -> Color {
+        Color::from_f32(v.x(), v.y(), v.z())
+    }
+
+    // threshold in [0,1) is added in units of one step before truncating, which turns banding into fine noise
+    pub fn from_v3_dithered(v: V3, threshold: f32) -> Color {
+        let quantize = |x: f32| (x.max(0.0) * 255.0 + threshold).min(255.0) as u8;
+        Color(quantize(v.x()), quantize(v.y()), quantize(v.z()))
+    }
+
+    // 8x8 Bayer matrix; ordered dithering keeps the output deterministic
+    pub fn bayer_threshold(x: i32, y: i32) -> f32 {
+        let (mut x, mut y) = (x as u32 & 7, y as u32 & 7);
+        let mut value = 0;
+        for _ in 0..3 {
+            value = (value << 2) | (((x & 1) ^ (y & 1)) << 1) | (y & 1);
+            x >>= 1;
+            y >>= 1;
+        }
+
+        // the finest level lands in the most significant bits, spreading neighbouring thresholds far apart
+        (value as f32 + 0.5) / 64.0
+    }
+}
+
+// per-channel absolute differences between two 8-bit images of the same size
+pub struct ImageDiff {
+    pub max: u8,
+    pub mean: f32,
+    pub changed_pixels: usize,
+    // changed pixels in red, brighter the larger the difference, over a dimmed gray copy of the first image
+    pub image: Vec<Color>,
+}
+
+pub fn compare_images(a: &[Color], b: &[Color]) -> ImageDiff {
+    assert_eq!(a.len(), b.len(), "images must have the same size");
+
+    let (mut max, mut total, mut changed_pixels) = (0, 0, 0);
+    let image = a.iter().zip(b.iter()).map(|(p, q)| {
+        let channels = [(p.red(), q.red()), (p.green(), q.green()), (p.blue(), q.blue())];
+        let diffs = channels.iter().map(|&(x, y)| x.abs_diff(y)).collect::<Vec<u8>>();
+        let diff = *diffs.iter().max().unwrap();
+        max = max.max(diff);
+        total += diffs.iter().map(|&d| d as u64).sum::<u64>();
+
+        if diff > 0 {
+            changed_pixels += 1;
+            Color(128 + diff / 2, 0, 0)
+        } else {
+            let gray = ((p.red() as u32 + p.green() as u32 + p.blue() as u32) / 12) as u8;
+            Color(gray, gray, gray)
+        }
+    }).collect();
+
+    ImageDiff {
+        max: max,
+        mean: total as f32 / (3 * a.len().max(1)) as f32,
+        changed_pixels: changed_pixels,
+        image: image,
+    }
+}
+
+// how composite lays a layer onto everything below it
+pub enum BlendOp {
+    Add,
+    Multiply,
+    // the layer holds premultiplied color with this per-pixel alpha, e.g. from render_rgba
+    Over(Vec<f32>),
+}
+
+// recombines linear float passes (direct and indirect, an emission-only pass, a subject over a background plate)
+// without rendering again; layers go bottom to top onto black, so the first one is usually Add
+pub fn composite(layers: &[(Vec<V3>, BlendOp)]) -> Vec<V3> {
+    let size = layers.first().map_or(0, |(layer, _)| layer.len());
+    let mut result = vec![V3(0.0, 0.0, 0.0); size];
+
+    for (layer, op) in layers {
+        assert_eq!(layer.len(), size, "layers must have the same size");
+        for (index, below) in result.iter_mut().enumerate() {
+            *below = match op {
+                BlendOp::Add => *below + layer[index],
+                BlendOp::Multiply => *below * layer[index],
+                BlendOp::Over(alpha) => layer[index] + below.scale(1.0 - alpha[index]),
+            };
+        }
+    }
+
+    result
+}
+
+// float rgb sums and a sample count per pixel. The renderer adds into it, and a live viewer or a denoiser can
+// read it between passes through render_progressive's callback
+pub struct FrameBuffer {
+    width: i32,
+    height: i32,
+    sums: Vec<V3>,
+    counts: Vec<u32>,
+}
+
+impl FrameBuffer {
+    pub fn new(width: i32, height: i32) -> FrameBuffer {
+        FrameBuffer::from_sums(width, height, vec![V3(0.0, 0.0, 0.0); (width * height) as usize], 0)
+    }
+
+    // every pixel has had `samples` samples added already, e.g. when resuming from a checkpoint
+    pub fn from_sums(width: i32, height: i32, sums: Vec<V3>, samples: u32) -> FrameBuffer {
+        FrameBuffer {
+            width: width,
+            height: height,
+            counts: vec![samples; sums.len()],
+            sums: sums,
+        }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    pub fn add_sample(&mut self, x: i32, y: i32, radiance: V3) {
+        let index = (y * self.width + x) as usize;
+        self.sums[index] = self.sums[index] + radiance;
+        self.counts[index] += 1;
+    }
+
+    pub fn sums(&self) -> &[V3] {
+        &self.sums
+    }
+
+    pub fn count(&self, x: i32, y: i32) -> u32 {
+        self.counts[(y * self.width + x) as usize]
+    }
+
+    // the averaged linear radiance, black while the pixel has no samples yet
+    pub fn mean(&self, x: i32, y: i32) -> V3 {
+        self.mean_at((y * self.width + x) as usize)
+    }
+
+    // every pixel's mean, as a float layer for composite
+    pub fn means(&self) -> Vec<V3> {
+        (0..self.sums.len()).map(|index| self.mean_at(index)).collect()
+    }
+
+    fn mean_at(&self, index: usize) -> V3 {
+        match self.counts[index] {
+            0 => V3(0.0, 0.0, 0.0),
+            n => self.sums[index].scale(1.0 / n as f32),
+        }
+    }
+
+    // 8-bit colors with the default tone map and gamma
+    pub fn resolve(&self) -> Vec<Color> {
+        self.resolve_with(&RenderSettings::default())
+    }
+
+    pub fn resolve_with(&self, settings: &RenderSettings) -> Vec<Color> {
+        (0..self.sums.len()).map(|index| self.quantize(index, self.mean_at(index), settings)).collect()
+    }
+
+    // 16 bits per channel through the same tone map, color space and gamma; fine enough that it needs no dither
+    pub fn resolve16(&self, settings: &RenderSettings) -> Vec<[u16; 3]> {
+        (0..self.sums.len()).map(|index| {
+            let encoded = FrameBuffer::encode(self.mean_at(index), settings);
+            let quantize = |x: f32| (x.max(0.0).min(1.0) * 65535.0 + 0.5) as u16;
+            [quantize(encoded.x()), quantize(encoded.y()), quantize(encoded.z())]
+        }).collect()
+    }
+
+    fn encode(color: V3, settings: &RenderSettings) -> V3 {
+        settings.gamma.encode(settings.output_space.from_linear_srgb(settings.tone_map.apply(color)))
+    }
+
+    fn quantize(&self, index: usize, color: V3, settings: &RenderSettings) -> Color {
+        let encoded = FrameBuffer::encode(color, settings);
+        if settings.dither {
+            let (i, j) = (index as i32 % self.width, index as i32 / self.width);
+            Color::from_v3_dithered(encoded, Color::bayer_threshold(i, j))
+        } else {
+            Color::from_v3(encoded)
+        }
+    }
+}
+
+pub struct RenderReport {
+    pub samples_per_pixel: i32,
+    pub elapsed: Duration,
+}
+
+// path traces scene through camera at the resolution and sample count of settings, lit by the scene's light
+// shape, and writes the result to output_path; NaN samples are dropped to black
+pub fn render(scene: &Scene, camera: &Camera, settings: &RenderSettings, output_path: &str) -> RenderReport {
+    scene_renderer(scene, camera, settings).render(output_path, settings)
+}
+
+// like render, but writes a 16-bit png for grading that would show the banding of 8 bits
+pub fn render_png16(scene: &Scene, camera: &Camera, settings: &RenderSettings, output_path: &str) -> RenderReport {
+    scene_renderer(scene, camera, settings).render_png16(output_path, settings)
+}
+
+// debug: colors every pixel by the light that the light-sampled directions of its paths reached most often (see
+// LightChoices::color), black where none was reached; settings.light_choices must be set
+pub fn render_light_choices(scene: &Scene, camera: &Camera, settings: &RenderSettings, output_path: &str) -> RenderReport {
+    let start = Instant::now();
+    let choices = settings.light_choices.as_ref().expect("render_light_choices needs settings.light_choices");
+    let renderer = scene_renderer(scene, camera, settings);
+
+    let mut colors = Vec::with_capacity((renderer.width * renderer.height) as usize);
+    for j in 0..renderer.height {
+        for i in 0..renderer.width {
+            choices.take();
+            for pass in 0..settings.samples_per_pixel {
+                let mut sampler = settings.sampler_for(pass as u32, (j * renderer.width + i) as u32);
+                (renderer.renderer)(i, j, &mut sampler);
+            }
+            colors.push(choices.take().first().map_or(V3(0.0, 0.0, 0.0), |&(id, _)| LightChoices::color(id)));
+        }
+    }
+
+    let frame = FrameBuffer::from_sums(renderer.width, renderer.height, colors, 1);
+    renderer.write_ppm(output_path, &frame, &RenderSettings::default());
+
+    RenderReport {
+        samples_per_pixel: settings.samples_per_pixel,
+        elapsed: start.elapsed(),
+    }
+}
+
+// like render, but traces settings.spectral_samples wavelengths per pixel sample, each camera ray carrying its
+// wavelength for the materials; the colors of the scene are spread over the spectrum with rgb_to_radiance
+pub fn render_spectral(scene: &Scene, camera: &Camera, settings: &RenderSettings, output_path: &str) -> RenderReport {
+    let (w, h) = (settings.width, settings.height);
+    assert!(camera.matches_resolution(w, h), "camera aspect doesn't match the output resolution");
+
+    let renderer = Renderer::new(settings, Box::new(|_, _, _| V3(0.0, 0.0, 0.0)));
+    renderer.render_spectral(output_path, settings, &|i, j, nm, sampler| {
+        match camera_ray(camera, w, h, i, j, sampler) {
+            Some(mut ray) => {
+                ray.wavelength = Some(nm);
+                let radiance = rgb_to_radiance(scene.color(ray, scene.lights().clone(), 0, settings, sampler), nm);
+                if radiance.is_nan() { 0.0 } else { radiance }
+            },
+            None => 0.0,
+        }
+    })
+}
+
+// the jittered ray through pixel (i, j) of a w x h image, None outside a fisheye's image circle
+fn camera_ray(camera: &Camera, w: i32, h: i32, i: i32, j: i32, sampler: &mut Samplers) -> Option<Ray> {
+    let (du, dv) = sampler.next_2d();
+    let u = (i as f32 + du) / w as f32;
+    let v = ((h - 1 - j) as f32 + dv) / h as f32;
+    if !camera.in_image(u, v) {
+        return None;
+    }
+    Some(camera.get_ray_sampled(u, v, sampler))
+}
+
+fn scene_renderer<'a>(scene: &'a Scene, camera: &'a Camera, settings: &'a RenderSettings) -> Renderer<'a> {
+    let (w, h) = (settings.width, settings.height);
+    assert!(camera.matches_resolution(w, h), "camera aspect doesn't match the output resolution");
+
+    Renderer::new(settings, Box::new(move |i, j, sampler| {
+        match camera_ray(camera, w, h, i, j, sampler) {
+            Some(ray) => scene.color(ray, scene.lights().clone(), 0, settings, sampler).map(&|t| if t.is_nan() { 0.0 } else { t }),
+            None => V3(0.0, 0.0, 0.0),
+        }
+    }))
+}
+
+// renders frames images to file_pattern with `{}` replaced by the frame index; frame returns the camera for a
+// frame and may move objects with Scene::set_figure first. The scene, and so every BVH in it, is built once, which
+// makes camera-only animations such as turntables cost little more than their frames
+pub fn render_sequence(scene: &mut Scene, frames: usize, settings: &RenderSettings, file_pattern: &str, frame: &mut FnMut(&mut Scene, usize) -> Camera) -> Vec<RenderReport> {
+    (0..frames).map(|index| {
+        let camera = frame(scene, index);
+        render(scene, &camera, settings, &file_pattern.replace("{}", &index.to_string()))
+    }).collect()
+}
+
+// renders one image per focus distance, to file_pattern with `{}` replaced by the index into distances; a
+// render_sequence of camera refocused at each distance
+pub fn focus_bracket(scene: &mut Scene, camera: &Camera, distances: &[f32], settings: &RenderSettings, file_pattern: &str) -> Vec<RenderReport> {
+    render_sequence(scene, distances.len(), settings, file_pattern, &mut |_, index| camera.with_focus_dist(distances[index]))
+}
+
+const CHECKPOINT_MAGIC: &[u8] = b"RUYTCKPT";
+
+// how accumulate writes the color image when there is no alpha
+#[derive(Clone, Copy)]
+enum OutputFormat {
+    Ppm,
+    Png16,
+}
+
+pub struct Renderer<'a> {
+    pub renderer: Box<Fn(i32,i32,&mut Samplers) -> V3 + 'a>,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl<'a> Renderer<'a> {
+    // the image size comes from the settings
+    pub fn new(settings: &RenderSettings, renderer: Box<Fn(i32,i32,&mut Samplers) -> V3 + 'a>) -> Renderer<'a> {
+        Renderer {
+            renderer: renderer,
+            width: settings.width,
+            height: settings.height,
+        }
+    }
+
+    pub fn render(&self, file_name: &str, settings: &RenderSettings) -> RenderReport {
+        self.render_progressive(file_name, settings, &mut |_, _| {})
+    }
+
+    // on_pass receives the frame buffer and the number of passes so far
+    pub fn render_progressive(&self, file_name: &str, settings: &RenderSettings, on_pass: &mut FnMut(&FrameBuffer, i32)) -> RenderReport {
+        let frame = FrameBuffer::new(self.width, self.height);
+        self.accumulate(file_name, settings, frame, 0, OutputFormat::Ppm, None, None, on_pass)
+    }
+
+    // an RGB png at 16 bits per channel instead of the 8-bit ppm; dither is ignored
+    pub fn render_png16(&self, file_name: &str, settings: &RenderSettings) -> RenderReport {
+        let frame = FrameBuffer::new(self.width, self.height);
+        self.accumulate(file_name, settings, frame, 0, OutputFormat::Png16, None, None, &mut |_, _| {})
+    }
+
+    // writes an RGBA png whose alpha is the fraction of primary samples that hit geometry; coverage gets a sampler
+    // in the same state as the renderer's, so with Halton both see the same pixel jitter. The color is divided
+    // by alpha (straight alpha), which assumes a black background so that misses add nothing
+    pub fn render_rgba(&self, file_name: &str, settings: &RenderSettings, coverage: &Fn(i32,i32,&mut Samplers) -> f32) -> RenderReport {
+        let frame = FrameBuffer::new(self.width, self.height);
+        self.accumulate(file_name, settings, frame, 0, OutputFormat::Ppm, Some(coverage), None, &mut |_, _| {})
+    }
+
+    // like render, but pixels on a geometric edge get settings.edge_samples extra samples every pass. probe
+    // returns the first hit through the center of a pixel, e.g. Scene::hit_id of an unjittered camera ray
+    pub fn render_edge_aware(&self, file_name: &str, settings: &RenderSettings, probe: &Fn(i32,i32) -> Option<(HitRecord, usize)>) -> RenderReport {
+        let edges = self.find_edges(probe);
+        let frame = FrameBuffer::new(self.width, self.height);
+        self.accumulate(file_name, settings, frame, 0, OutputFormat::Ppm, None, Some(&edges), &mut |_, _| {})
+    }
+
+    // a pixel is on an edge when a 4-neighbour hits something else, or the same object with a different normal
+    // or at a noticeably different depth
+    pub fn find_edges(&self, probe: &Fn(i32,i32) -> Option<(HitRecord, usize)>) -> Vec<bool> {
+        let hits = (0..self.height).flat_map(|j| (0..self.width).map(move |i| (i, j)))
+            .map(|(i, j)| probe(i, j))
+            .collect::<Vec<Option<(HitRecord, usize)>>>();
+        let differs = |a: &Option<(HitRecord, usize)>, b: &Option<(HitRecord, usize)>| match (a, b) {
+            (Some((ra, ia)), Some((rb, ib))) => {
+                ia != ib
+                    || ra.normal.dot(rb.normal) < EDGE_NORMAL_COS
+                    || (ra.at - rb.at).abs() > EDGE_DEPTH_RATIO * ra.at.min(rb.at)
+            },
+            (None, None) => false,
+            _ => true,
+        };
+
+        (0..self.height).flat_map(|j| (0..self.width).map(move |i| (i, j))).map(|(i, j)| {
+            let center = &hits[(j * self.width + i) as usize];
+            [(-1, 0), (1, 0), (0, -1), (0, 1)].iter()
+                .map(|(di, dj)| (i + di, j + dj))
+                .filter(|&(x, y)| 0 <= x && x < self.width && 0 <= y && y < self.height)
+                .any(|(x, y)| differs(center, &hits[(y * self.width + x) as usize]))
+        }).collect()
+    }
+
+    // keeps the accumulated sums in checkpoint_path after every pass and picks them up again on the next run,
+    // so an interrupted render continues where it stopped; samples_per_pixel counts the resumed passes too
+    pub fn render_resumable(&self, file_name: &str, checkpoint_path: &str, settings: &RenderSettings) -> RenderReport {
+        let (frame, passes) = self.load_checkpoint(checkpoint_path)
+            .unwrap_or_else(|| (FrameBuffer::new(self.width, self.height), 0));
+        if settings.time_budget.is_none() && passes >= settings.samples_per_pixel {
+            self.write_ppm(file_name, &frame, settings);
+            return RenderReport {
+                samples_per_pixel: passes,
+                elapsed: Duration::from_secs(0),
+            };
+        }
+
+        self.accumulate(file_name, settings, frame, passes, OutputFormat::Ppm, None, None, &mut |frame, passes| {
+            self.save_checkpoint(checkpoint_path, frame.sums(), passes).unwrap();
+        })
+    }
+
+    fn save_checkpoint(&self, checkpoint_path: &str, buffer: &[V3], passes: i32) -> io::Result<()> {
+        // written next to the checkpoint and renamed, so an interruption never leaves a torn file behind
+        let temporary = format!("{}.tmp", checkpoint_path);
+        {
+            let mut f = BufWriter::new(fs::File::create(&temporary)?);
+            f.write_all(CHECKPOINT_MAGIC)?;
+            for n in &[self.width, self.height, passes] {
+                f.write_all(&n.to_le_bytes())?;
+            }
+            for v in buffer {
+                for c in &v.as_array() {
+                    f.write_all(&c.to_le_bytes())?;
+                }
+            }
+        }
+
+        fs::rename(temporary, checkpoint_path)
+    }
+
+    // None when there is no checkpoint or it belongs to a different resolution
+    fn load_checkpoint(&self, checkpoint_path: &str) -> Option<(FrameBuffer, i32)> {
+        let bytes = fs::read(checkpoint_path).ok()?;
+        let rest = bytes.strip_prefix(CHECKPOINT_MAGIC)?;
+        let words = rest.chunks_exact(4).map(|c| [c[0], c[1], c[2], c[3]]).collect::<Vec<[u8; 4]>>();
+        if words.len() < 3 {
+            return None;
+        }
+
+        let (width, height, passes) = (i32::from_le_bytes(words[0]), i32::from_le_bytes(words[1]), i32::from_le_bytes(words[2]));
+        let values = words[3..].iter().map(|w| f32::from_le_bytes(*w)).collect::<Vec<f32>>();
+        if width != self.width || height != self.height || values.len() != (width * height * 3) as usize {
+            return None;
+        }
+
+        let sums = values.chunks(3).map(|c| V3(c[0], c[1], c[2])).collect();
+        Some((FrameBuffer::from_sums(width, height, sums, passes as u32), passes))
+    }
+
+    fn accumulate(&self, file_name: &str, settings: &RenderSettings, mut frame: FrameBuffer, mut passes: i32, format: OutputFormat, coverage: Option<&Fn(i32,i32,&mut Samplers) -> f32>, edges: Option<&[bool]>, on_pass: &mut FnMut(&FrameBuffer, i32)) -> RenderReport {
+        let start = Instant::now();
+        let mut alpha = coverage.map(|_| vec![0.0; frame.sums().len()]);
+
+        loop {
+            for j in 0..self.height {
+                for i in 0..self.width {
+                    let index = (j * self.width + i) as usize;
+                    // edge pixels number their samples so that every pass gets fresh jitter positions
+                    let extra = match edges {
+                        Some(edges) if edges[index] => settings.edge_samples,
+                        _ => 0,
+                    };
+                    for k in 0..=extra {
+                        let mut sampler = settings.sampler_for((passes * (extra + 1) + k) as u32, index as u32);
+                        frame.add_sample(i, j, (self.renderer)(i,j,&mut sampler));
+                    }
+
+                    if let (Some(coverage), Some(alpha)) = (coverage, alpha.as_mut()) {
+                        let mut sampler = settings.sampler_for(passes as u32, index as u32);
+                        alpha[index] += coverage(i,j,&mut sampler);
+                    }
+                }
+            }
+            passes += 1;
+
+            if let Some(guide) = &settings.path_guide {
+                guide.refresh();
+            }
+            if let Some(cache) = &settings.irradiance_cache {
+                cache.refresh();
+            }
+
+            on_pass(&frame, passes);
+
+            let finished = match settings.time_budget {
+                Some(budget) => start.elapsed() >= budget,
+                None => passes >= settings.samples_per_pixel,
+            };
+            if finished {
+                break;
+            }
+
+            if settings.write_every_pass {
+                self.write_output(file_name, &frame, alpha.as_ref(), passes, format, settings);
+            }
+        }
+
+        self.write_output(file_name, &frame, alpha.as_ref(), passes, format, settings);
+
+        RenderReport {
+            samples_per_pixel: passes,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    // pixel returns the radiance carried at the given wavelength in nm. Every pixel sample traces
+    // settings.spectral_samples wavelengths, one per equal slice of the visible range at a shared random offset, and
+    // they are accumulated through the color matching functions; the renderer closure isn't used
+    pub fn render_spectral(&self, file_name: &str, settings: &RenderSettings, pixel: &Fn(i32, i32, f32, &mut Samplers) -> f32) -> RenderReport {
+        let start = Instant::now();
+        let n = settings.spectral_samples.max(1);
+        let mut image = SpectralImage::new(self.width as usize, self.height as usize);
+        for pass in 0..settings.samples_per_pixel {
+            for j in 0..self.height {
+                for i in 0..self.width {
+                    let index = (j * self.width + i) as u32;
+                    // drawn from a sample index past the ones the wavelengths use, so it is shared by them, follows
+                    // the render seed and is independent of their jitter
+                    let offset = settings.sampler_for((settings.samples_per_pixel * n + pass) as u32, index).next_2d().0;
+                    for k in 0..n {
+                        let nm = LAMBDA_MIN + (LAMBDA_MAX - LAMBDA_MIN) * (k as f32 + offset) / n as f32;
+                        let mut sampler = settings.sampler_for((pass * n + k) as u32, index);
+                        image.add_sample(i as usize, j as usize, nm, pixel(i, j, nm, &mut sampler));
+                    }
+                }
+            }
+        }
+
+        let frame = FrameBuffer::from_sums(self.width, self.height, image.resolve(), 1);
+        self.write_ppm(file_name, &frame, settings);
+
+        RenderReport {
+            samples_per_pixel: settings.samples_per_pixel,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    // for renderers returning a scalar in every channel, such as Scene::path_length: writes the per-pixel mean
+    // normalized by the image maximum, through a black-red-yellow-white ramp so deep regions stand out
+    pub fn render_heatmap(&self, file_name: &str, settings: &RenderSettings) -> RenderReport {
+        let start = Instant::now();
+        let mut frame = FrameBuffer::new(self.width, self.height);
+        for pass in 0..settings.samples_per_pixel {
+            for j in 0..self.height {
+                for i in 0..self.width {
+                    let mut sampler = settings.sampler_for(pass as u32, (j * self.width + i) as u32);
+                    frame.add_sample(i, j, (self.renderer)(i,j,&mut sampler));
+                }
+            }
+        }
+
+        let values = (0..self.height).flat_map(|j| (0..self.width).map(move |i| (i, j)))
+            .map(|(i, j)| frame.mean(i, j).x())
+            .collect::<Vec<f32>>();
+        let max = values.iter().cloned().fold(0.0, f32::max);
+
+        // the ramp colors are display values already, so they are written without tone map or gamma
+        let ramp = values.into_iter().map(|value| {
+            let t = if max > 0.0 { value / max } else { 0.0 };
+            V3((3.0 * t).max(0.0), (3.0 * t - 1.0).max(0.0), (3.0 * t - 2.0).max(0.0))
+        }).collect();
+        let heatmap = FrameBuffer::from_sums(self.width, self.height, ramp, 1);
+        self.write_ppm(file_name, &heatmap, &RenderSettings { gamma: GammaMode::None, ..RenderSettings::default() });
+
+        RenderReport {
+            samples_per_pixel: settings.samples_per_pixel,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    fn write_output(&self, file_name: &str, frame: &FrameBuffer, alpha: Option<&Vec<f32>>, passes: i32, format: OutputFormat, settings: &RenderSettings) {
+        match (alpha, format) {
+            (Some(alpha), _) => self.write_rgba_png(file_name, frame, alpha, passes, settings),
+            (None, OutputFormat::Ppm) => self.write_ppm(file_name, frame, settings),
+            (None, OutputFormat::Png16) => self.write_png16(file_name, frame, settings),
+        }
+    }
+
+    fn write_png16(&self, file_name: &str, frame: &FrameBuffer, settings: &RenderSettings) {
+        let values = frame.resolve16(settings).concat();
+        ::image::ImageBuffer::<::image::Rgb<u16>, Vec<u16>>::from_raw(self.width as u32, self.height as u32, values).unwrap().save(file_name).unwrap();
+    }
+
+    fn write_rgba_png(&self, file_name: &str, frame: &FrameBuffer, alpha: &[f32], passes: i32, settings: &RenderSettings) {
+        let mut bytes = Vec::with_capacity(alpha.len() * 4);
+        for (index, a) in alpha.iter().enumerate() {
+            let a = a / passes as f32;
+            let straight = if a > 0.0 { frame.mean_at(index).scale(1.0 / a) } else { V3(0.0, 0.0, 0.0) };
+            let c = frame.quantize(index, straight, settings);
+            bytes.extend_from_slice(&[c.red(), c.green(), c.blue(), (a.min(1.0) * 255.99) as u8]);
+        }
+
+        ::image::RgbaImage::from_raw(self.width as u32, self.height as u32, bytes).unwrap().save(file_name).unwrap();
+    }
+
+    fn write_ppm(&self, file_name: &str, frame: &FrameBuffer, settings: &RenderSettings) {
+        let mut f = BufWriter::new(fs::File::create(file_name).unwrap());
+        writeln!(f, "P3\n{} {}\n255", self.width, self.height).unwrap();
+
+        for c in frame.resolve_with(settings) {
+            writeln!(
+                f,
+                "{} {} {}",
+                c.red(),
+                c.green(),
+                c.blue(),
+            ).unwrap();
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GammaMode {
+    // square root, i.e. gamma 2
+    Gamma2,
+    // writes linear values (clamped to [0,1]) for color-managed pipelines; the ppm is then linear-encoded
+    None,
+}
+
+impl GammaMode {
+    fn encode(&self, color: V3) -> V3 {
+        match self {
+            GammaMode::Gamma2 => color.map(&|x| x.sqrt()),
+            GammaMode::None => color,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ToneMap {
+    // clamps at 1.0
+    None,
+    // L / (1 + L) on luminance; never reaches 1.0, so highlights come out gray
+    Reinhard,
+    // L (1 + L / w^2) / (1 + L): the luminance w maps exactly to 1.0 and midtones keep their contrast
+    ReinhardExtended(f32),
+    // the filmic ACES output transform, to match references graded through an ACES pipeline
+    Aces,
+}
+
+impl ToneMap {
+    fn apply(&self, color: V3) -> V3 {
+        let luminance = 0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z();
+        let mapped = match self {
+            ToneMap::None => return color,
+            ToneMap::Aces => return aces_output_transform(color),
+            ToneMap::Reinhard => luminance / (1.0 + luminance),
+            ToneMap::ReinhardExtended(white_point) => {
+                luminance * (1.0 + luminance / (white_point * white_point)) / (1.0 + luminance)
+            },
+        };
+
+        // scaling all channels by the luminance ratio keeps the hue and saturation of bright colors
+        if luminance > 0.0 { color.scale(mapped / luminance) } else { color }
+    }
+}
+
+// the clamp on reflected radiance at regularization_depth, widening with every bounce after it, and the
+// roughness smooth metals get from there on
+const REGULARIZATION_LIMIT: f32 = 20.0;
+const REGULARIZATION_ROUGHNESS: f32 = 0.2;
+
+// bounces before a path is cut off; reference renders go far enough that the lost energy is negligible even in
+// bright closed scenes
+const MAX_DEPTH: i32 = 50;
+const REFERENCE_MAX_DEPTH: i32 = 500;
+
+// neighbouring first hits count as one surface within these
+const EDGE_NORMAL_COS: f32 = 0.9;
+const EDGE_DEPTH_RATIO: f32 = 0.05;
+
+pub struct RenderSettings {
+    pub width: i32,
+    pub height: i32,
+    pub samples_per_pixel: i32,
+    // varies the per-pixel sample patterns, so renders with different seeds have independent noise
+    pub seed: u32,
+    // renders whole-image passes until the budget elapses instead of stopping at samples_per_pixel
+    pub time_budget: Option<Duration>,
+    // rewrite the output file with the running average after every pass
+    pub write_every_pass: bool,
+    pub path_guide: Option<PathGuide>,
+    // shadow rays per diffuse hit; above 1 the light shape is sampled explicitly and only one BRDF continuation is
+    // traced, the two combined by multiple importance sampling
+    pub light_samples: usize,
+    // when set, primary rays only see this object; the rest of the scene still occludes and bounces light
+    pub matte_object: Option<usize>,
+    // drives pixel jitter, lens and light samples; Halton converges faster than pure random
+    pub sampler: SamplerKind,
+    // extra samples per pass for the pixels render_edge_aware finds on a silhouette or crease; sharpens edges
+    // without raising samples_per_pixel over the flat regions
+    pub edge_samples: i32,
+    // wavelengths traced per pixel sample by render_spectral
+    pub spectral_samples: i32,
+    // ground truth for checking the faster modes against: ignores the irradiance cache, regularization, indirect
+    // clamp, ambient fill and min_roughness and traces paths much deeper. The result is unbiased but slow and needs a lot of
+    // samples to converge
+    pub reference_mode: bool,
+    // experimental: reuses converged diffuse interreflection on secondary hits; biased, so off by default
+    pub irradiance_cache: Option<IrradianceCache>,
+    // from this bounce on, reflected radiance is clamped, widening the deeper the path goes, and smooth metals are
+    // roughened; trades a little energy for much fewer caustic fireflies
+    pub regularization_depth: Option<i32>,
+    // applied to the averaged radiance before gamma
+    pub tone_map: ToneMap,
+    // primaries of the written values; use AcesCg with GammaMode::None to hand linear ACEScg to a compositor
+    pub output_space: ColorSpace,
+    pub gamma: GammaMode,
+    // per-channel limit on light reflected by anything but the first visible surface, which removes fireflies
+    // from the indirect bounces while leaving direct lighting and highlights untouched
+    pub indirect_clamp: Option<f32>,
+    // raises the roughness of mirror-like metals to at least this, e.g. 0.1, and shades them as GGX so they take
+    // part in light sampling; blurs their reflections slightly (a bias) but takes out most of the fireflies of
+    // paths that find a small light through a mirror. Glass has no rough model and stays smooth. 0 is off
+    pub min_roughness: f32,
+    // share of diffuse bounces aimed at the light shape instead of following the BRDF; raise it for small bright
+    // lights that BRDF samples rarely find, lower it when most light comes from large or environment sources
+    pub light_sample_fraction: f32,
+    // next-event estimation: aims diffuse bounces and shadow rays at the light shape and the environment map. Off,
+    // every bounce follows the material alone and lights are only found by chance; the image converges to the
+    // same result with much more noise, which is useful to show what light sampling buys or to rule it out in a bug
+    pub use_nee: bool,
+    // constant light reflected by every diffuse hit on top of the traced lighting, scaled by the albedo. Not
+    // physically based (it ignores occlusion and adds energy at every bounce), so black by default; a small value
+    // gives a quick evenly lit look at geometry without setting up lights
+    pub ambient: V3,
+    // shades every non-emissive surface with this material (e.g. a gray lambertian for a clay render)
+    pub override_material: Option<Materials>,
+    // ordered dithering before the 8-bit quantization, so smooth gradients don't band
+    pub dither: bool,
+    // debug: records the light every light-sampled direction reaches, for render_light_choices; costs an extra
+    // intersection per diffuse bounce, so None outside of debugging
+    pub light_choices: Option<LightChoices>,
+}
+
+impl RenderSettings {
+    // also restarts the shared random stream, so everything the sample draws follows the seed, pass and pixel
+    pub fn sampler_for(&self, index: u32, pixel: u32) -> Samplers {
+        let pixel = pixel.wrapping_add(self.seed.wrapping_mul(0x9e37_79b9));
+        reseed_stream(sample_seed(index, pixel) ^ 0x5851_f42d_4c95_7f2d);
+        Samplers::new(self.sampler, index, pixel)
+    }
+
+    fn max_depth(&self) -> i32 {
+        if self.reference_mode { REFERENCE_MAX_DEPTH } else { MAX_DEPTH }
+    }
+
+    // reflected radiance at depth >= 1 only reaches the camera after another bounce, i.e. it is indirect light
+    fn clamp_indirect(&self, reflected: V3, depth: i32) -> V3 {
+        match self.indirect_clamp {
+            Some(limit) if depth >= 1 && !self.reference_mode => reflected.map(&|x| x.min(limit)),
+            _ => reflected,
+        }
+    }
+
+    // the fireflies come from the first bounces past start, where a path still carries most of its throughput;
+    // deeper bounces are dimmed by every surface before them and get a wider limit, so they keep their energy
+    fn regularize(&self, radiance: V3, depth: i32) -> V3 {
+        match self.regularization_depth {
+            Some(start) if depth >= start && !self.reference_mode => {
+                let limit = REGULARIZATION_LIMIT * (depth - start + 1) as f32;
+                radiance.map(&|x| x.min(limit))
+            },
+            _ => radiance,
+        }
+    }
+
+    // the roughness smooth metals are raised to at depth, from min_roughness and regularization
+    fn min_roughness_at(&self, depth: i32) -> f32 {
+        match self.regularization_depth {
+            _ if self.reference_mode => 0.0,
+            Some(start) if depth >= start => self.min_roughness.max(REGULARIZATION_ROUGHNESS),
+            _ => self.min_roughness,
+        }
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            width: 400,
+            height: 250,
+            samples_per_pixel: 100,
+            seed: 0,
+            time_budget: None,
+            write_every_pass: false,
+            path_guide: None,
+            light_samples: 1,
+            matte_object: None,
+            sampler: SamplerKind::Random,
+            edge_samples: 0,
+            spectral_samples: 4,
+            reference_mode: false,
+            irradiance_cache: None,
+            regularization_depth: None,
+            tone_map: ToneMap::None,
+            output_space: ColorSpace::LinearSrgb,
+            gamma: GammaMode::Gamma2,
+            indirect_clamp: None,
+            min_roughness: 0.0,
+            light_sample_fraction: 0.5,
+            use_nee: true,
+            ambient: V3(0.0, 0.0, 0.0),
+            override_material: None,
+            dither: false,
+            light_choices: None,
+        }
+    }
+}
+
+// what goes inside Scene::cornell_box besides the walls and the ceiling light
+pub enum CornellContents {
+    Empty,
+    // the short and the tall box of the original reference scene
+    TwoBoxes,
+    // a glass sphere in front of the tall box
+    GlassSphere,
+    Custom(Vec<Objects>),
+}
+
+// auxiliary outputs of Scene::aov; normals are encoded from [-1,1] to [0,1] per channel and misses are black
+#[derive(Clone, Copy, PartialEq)]
+pub enum Aov {
+    WorldNormal,
+    // x right, y up and z towards the camera, as compositors expect for relighting
+    CameraNormal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SceneWarning {
+    // object is the index into the scene's objects
+    Figure { object: usize, issue: FigureIssue },
+    ZeroAreaLight { object: usize },
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum RayKind {
+    // camera rays
+    Primary,
+    // explicit light samples
+    Shadow,
+    // every other bounce, specular or diffuse
+    Reflection,
+}
+
+#[derive(Clone, Copy)]
+struct Visibility {
+    primary: bool,
+    shadow: bool,
+    reflection: bool,
+}
+
+impl Visibility {
+    fn allows(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Primary => self.primary,
+            RayKind::Shadow => self.shadow,
+            RayKind::Reflection => self.reflection,
+        }
+    }
+}
+
+impl Default for Visibility {
+    fn default() -> Visibility {
+        Visibility {
+            primary: true,
+            shadow: true,
+            reflection: true,
+        }
+    }
+}
+
+const OBJECT_BVH_LEAF_SIZE: usize = 4;
+
+// hierarchy over object indices, so that Scene::hit_id only tests the objects along the ray
+enum ObjectBvh {
+    Leaf(Aabb, Vec<usize>),
+    Node(Aabb, Box<ObjectBvh>, Box<ObjectBvh>),
+}
+
+impl ObjectBvh {
+    fn new(mut items: Vec<(usize, Aabb)>) -> ObjectBvh {
+        let bbox = items.iter().skip(1).fold(items[0].1.clone(), |acc, (_, b)| acc.surround(b));
+        if items.len() <= OBJECT_BVH_LEAF_SIZE {
+            return ObjectBvh::Leaf(bbox, items.into_iter().map(|(id, _)| id).collect());
+        }
+
+        // split at the median centroid along the longest axis
+        let extent = bbox.max() - bbox.min();
+        let axis = if extent.x() >= extent.y() && extent.x() >= extent.z() { 0 } else if extent.y() >= extent.z() { 1 } else { 2 };
+        let key = |b: &Aabb| {
+            let c = b.min() + b.max();
+            if axis == 0 { c.x() } else if axis == 1 { c.y() } else { c.z() }
+        };
+        items.sort_by(|(_, a), (_, b)| key(a).partial_cmp(&key(b)).unwrap_or(std::cmp::Ordering::Equal));
+
+        let latter = items.split_off(items.len() / 2);
+        ObjectBvh::Node(bbox, Box::new(ObjectBvh::new(items)), Box::new(ObjectBvh::new(latter)))
+    }
+
+    fn hit(&self, objects: &[Objects], ray: &Ray, t_min: f32, closest: &mut f32, record: &mut Option<(HitRecord, usize)>, visible: &Fn(usize) -> bool) {
+        match self {
+            ObjectBvh::Leaf(bbox, ids) => {
+                if !bbox.hit(ray, t_min, *closest) {
+                    return;
+                }
+                for &id in ids {
+                    if !visible(id) {
+                        continue;
+                    }
+                    if let Some(rec) = objects[id].figure.hit(ray, t_min, *closest) {
+                        *closest = rec.at;
+                        *record = Some((rec, id));
+                    }
+                }
+            },
+            ObjectBvh::Node(bbox, left, right) => {
+                if bbox.hit(ray, t_min, *closest) {
+                    left.hit(objects, ray, t_min, closest, record, visible);
+                    right.hit(objects, ray, t_min, closest, record, visible);
+                }
+            },
+        }
+    }
+}
+
+pub struct Scene {
+    objects: Vec<Objects>,
+    // shapes worth sampling directly for next event estimation
+    lights: Figures,
+    bbox: Option<Aabb>,
+    accel: Option<ObjectBvh>,
+    // objects without a bounding box (e.g. infinite planes) are tested on every ray
+    unbounded: Vec<usize>,
+    // per object, indexed like objects
+    visibility: Vec<Visibility>,
+    background: Backgrounds,
+    atmosphere: Option<(f32, V3)>,
+    // closest hits per object, indexed like objects; only counted after with_hit_statistics
+    hit_counts: Option<Vec<AtomicUsize>>,
+    // some light has a focused emission profile, so light sampling is weighted by it (see light_reach)
+    focused_lights: bool,
+}
+
+impl Scene {
+    pub fn new(objects: Vec<Objects>) -> Scene {
+        let mut scene = Scene {
+            visibility: vec![Visibility::default(); objects.len()],
+            focused_lights: objects.iter().any(|object| object.material.is_focused_light()),
+            objects: objects,
+            lights: Figures::Figures(vec![]),
+            bbox: None,
+            accel: None,
+            unbounded: vec![],
+            background: Backgrounds::Black,
+            atmosphere: None,
+            hit_counts: None,
+        };
+        scene.build_accel();
+        scene
+    }
+
+    // the hierarchy over the objects; each figure keeps its own BVH, so this is cheap next to building those
+    fn build_accel(&mut self) {
+        let mut bbox: Option<Aabb> = None;
+        let mut bounded = vec![];
+        let mut unbounded = vec![];
+        for (id, object) in self.objects.iter().enumerate() {
+            match object.figure.bounding_box(0.0, 1.0) {
+                Some(b) => {
+                    bbox = Some(match bbox {
+                        Some(acc) => acc.surround(&b),
+                        None => b.clone(),
+                    });
+                    bounded.push((id, b));
+                },
+                None => {
+                    unbounded.push(id);
+                },
+            }
+        }
+
+        self.bbox = bbox;
+        self.accel = if bounded.is_empty() { None } else { Some(ObjectBvh::new(bounded)) };
+        self.unbounded = unbounded;
+    }
+
+    // moves or reshapes an object between frames of a sequence; the object hierarchy is rebuilt around it while
+    // every other figure's BVH is reused as is
+    pub fn set_figure(&mut self, object: usize, figure: Figures) {
+        self.objects[object].figure = figure;
+        self.build_accel();
+    }
+
+    // counts the closest hits of every ray per object, to see where intersection work goes; costs an atomic
+    // increment per hit
+    pub fn with_hit_statistics(mut self) -> Scene {
+        self.hit_counts = Some(self.objects.iter().map(|_| AtomicUsize::new(0)).collect());
+        self
+    }
+
+    // (object id, closest hits so far), most hit first
+    pub fn hit_statistics(&self) -> Option<Vec<(usize, usize)>> {
+        let counts = self.hit_counts.as_ref()?;
+        let mut statistics = counts.iter().map(|c| c.load(Ordering::Relaxed)).enumerate().collect::<Vec<(usize, usize)>>();
+        statistics.sort_by(|a, b| b.1.cmp(&a.1));
+        Some(statistics)
+    }
+
+    pub fn validate(&self) -> Vec<SceneWarning> {
+        let mut warnings = vec![];
+        for (id, object) in self.objects.iter().enumerate() {
+            let issues = object.figure.issues();
+            let degenerate = issues.iter().any(|issue| match issue {
+                FigureIssue::ZeroRadiusSphere | FigureIssue::DegenerateRect | FigureIssue::DegenerateTriangle => true,
+                _ => false,
+            });
+            if object.material.is_emissive() && degenerate {
+                warnings.push(SceneWarning::ZeroAreaLight { object: id });
+            }
+
+            warnings.extend(issues.into_iter().map(|issue| SceneWarning::Figure { object: id, issue: issue }));
+        }
+
+        warnings
+    }
+
+    // union of every bounded object; unbounded figures are skipped
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox.clone()
+    }
+
+    pub fn with_lights(mut self, lights: Figures) -> Scene {
+        self.lights = lights;
+        self
+    }
+
+    pub fn lights(&self) -> &Figures {
+        &self.lights
+    }
+
+    pub fn uniform_background(mut self, color: V3) -> Scene {
+        self.background = Backgrounds::uniform(color);
+        self
+    }
+
+    pub fn cubemap_background(mut self, faces: [Image; 6]) -> Scene {
+        self.background = Backgrounds::cubemap(faces);
+        self
+    }
+
+    // adds an infinite lambertian floor at height y, checkered in squares of side scale; a flat replacement for
+    // the huge ground sphere of create_random_scene
+    pub fn checkered_ground(mut self, y: f32, scale: f32, color_a: V3, color_b: V3) -> Scene {
+        self.objects.push(Objects {
+            figure: Figures::plane(V3(0.0, y, 0.0), V3(0.0, 1.0, 0.0)),
+            material: Materials::lambertian(Textures::uv_checker(Textures::solid(color_a), Textures::solid(color_b), scale)),
+        });
+        self.visibility.push(Visibility::default());
+        self.build_accel();
+        self
+    }
+
+    pub fn sky_background(mut self) -> Scene {
+        self.background = Backgrounds::Sky;
+        self
+    }
+
+    // physically based daylight; pair it with a sun light in sun_dir for outdoor scenes
+    pub fn preetham_sky(mut self, sun_dir: V3, turbidity: f32) -> Scene {
+        self.background = Backgrounds::preetham(sun_dir, turbidity);
+        self
+    }
+
+    // equirectangular map, importance sampled by luminance on diffuse hits
+    pub fn environment_background(mut self, image: Image) -> Scene {
+        self.background = Backgrounds::environment(image);
+        self
+    }
+
+    // 1 when the ray hits any geometry; the per-pixel average is the alpha of an RGBA render
+    pub fn coverage(&self, ray: &Ray) -> f32 {
+        if self.hit_id(ray, 0.001, std::f32::MAX).is_some() { 1.0 } else { 0.0 }
+    }
+
+    // only what the first hit emits, on black: a near instant preview of where the lights are and how bright
+    pub fn emission(&self, ray: &Ray) -> V3 {
+        match self.hit(ray, 0.001, std::f32::MAX) {
+            Some((rec, object)) => object.material.emitted_along(ray, &rec) + rec.emission,
+            None => V3(0.0, 0.0, 0.0),
+        }
+    }
+
+    // the first hit's normal, for the camera the ray came from
+    pub fn aov(&self, ray: &Ray, aov: Aov, camera: &Camera) -> V3 {
+        match self.hit(ray, 0.001, std::f32::MAX) {
+            Some((rec, _)) => {
+                let normal = match aov {
+                    Aov::WorldNormal => rec.normal,
+                    Aov::CameraNormal => camera.to_camera_space(rec.normal),
+                };
+                (normal + V3(1.0, 1.0, 1.0)).scale(0.5)
+            },
+            None => V3(0.0, 0.0, 0.0),
+        }
+    }
+
+    // colors missed rays by their direction, handy for checking where a camera is actually looking
+    pub fn debug_direction_background(mut self) -> Scene {
+        self.background = Backgrounds::Direction;
+        self
+    }
+
+    // e.g. an object that casts shadows but doesn't show up to the camera; the shadow flag applies to rays aimed at
+    // a light: the explicit light samples taken when light_samples > 1 and the bounces drawn from the light or
+    // environment pdf. Every other bounce uses the reflection flag
+    pub fn with_visibility(mut self, object: usize, primary: bool, shadow: bool, reflection: bool) -> Scene {
+        self.visibility[object] = Visibility {
+            primary: primary,
+            shadow: shadow,
+            reflection: reflection,
+        };
+        self
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(HitRecord, &Objects)> {
+        self.hit_id(ray, t_min, t_max).map(|(rec, id)| (rec, &self.objects[id]))
+    }
+
+    pub fn hit_id(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(HitRecord, usize)> {
+        self.hit_visible(ray, t_min, t_max, &|_| true)
+    }
+
+    // like hit_id, but skips objects whose visibility excludes this kind of ray
+    fn hit_id_as(&self, ray: &Ray, t_min: f32, t_max: f32, kind: RayKind) -> Option<(HitRecord, usize)> {
+        self.hit_visible(ray, t_min, t_max, &|id| self.visibility[id].allows(kind))
+    }
+
+    fn hit_visible(&self, ray: &Ray, t_min: f32, t_max: f32, visible: &Fn(usize) -> bool) -> Option<(HitRecord, usize)> {
+        let mut closest_parameter = t_max;
+        let mut record = None;
+
+        for &id in &self.unbounded {
+            if !visible(id) {
+                continue;
+            }
+            if let Some(rec) = self.objects[id].figure.hit(ray, t_min, closest_parameter) {
+                closest_parameter = rec.at;
+                record = Some((rec,id));
+            }
+        }
+        if let Some(accel) = &self.accel {
+            accel.hit(&self.objects, ray, t_min, &mut closest_parameter, &mut record, visible);
+        }
+
+        if let (Some(counts), Some((_, id))) = (&self.hit_counts, &record) {
+            counts[*id].fetch_add(1, Ordering::Relaxed);
+        }
+
+        record
+    }
+
+    // exponential distance fog; rays that escape to the background have crossed an infinite stretch of it, so the
+    // sky behind distant objects fades into the fog color with them
+    pub fn with_atmosphere(mut self, density: f32, color: V3) -> Scene {
+        self.atmosphere = Some((density, color));
+        self
+    }
+
+    fn through_atmosphere(&self, radiance: V3, distance: f32) -> V3 {
+        match self.atmosphere {
+            Some((density, color)) => {
+                // a zero density would make an infinite distance NaN
+                let transmittance = if density > 0.0 { (-density * distance).exp() } else { 1.0 };
+                radiance.scale(transmittance) + color.scale(1.0 - transmittance)
+            },
+            None => radiance,
+        }
+    }
+
+    pub fn merge(mut self, other: Scene) -> Scene {
+        self.objects.extend(other.objects);
+        self.visibility.extend(other.visibility);
+        // an empty light list would otherwise take its share of the light samples without contributing anything
+        let lights = match (self.lights.is_empty(), other.lights.is_empty()) {
+            (true, _) => other.lights,
+            (_, true) => self.lights,
+            _ => Figures::Figures(vec![self.lights, other.lights]),
+        };
+        let mut merged = Scene::new(self.objects).with_lights(lights);
+        merged.visibility = self.visibility;
+        merged.background = self.background;
+        merged.atmosphere = self.atmosphere;
+        if self.hit_counts.is_some() || other.hit_counts.is_some() {
+            merged = merged.with_hit_statistics();
+        }
+        merged
+    }
+
+    // builds a scene from trait objects instead of Figures; each object is still wrapped in Figures::Custom so
+    // the BVH, lights and visibility work unchanged, at the cost of a dynamic call per primitive test
+    pub fn from_dyn(objects: Vec<(Box<dyn Hit + Send + Sync>, Materials)>) -> Scene {
+        Scene::new(objects.into_iter().map(|(figure, material)| Objects {
+            figure: Figures::Custom(Arc::from(figure)),
+            material: material,
+        }).collect())
+    }
+
+    // a lambertian card inside a uniformly emitting box; its outgoing radiance should converge to albedo * radiance
+    pub fn gray_card(albedo: V3, radiance: f32) -> Scene {
+        Scene::new(vec![
+            Objects {
+                figure: Figures::xz_rect(-1.0, 1.0, -1.0, 1.0, 0.0),
+                material: Materials::lambertian(Textures::solid(albedo)),
+            },
+            Objects {
+                figure: Figures::cuboid(V3(-10.0, -10.0, -10.0), V3(10.0, 10.0, 10.0)),
+                material: Materials::diffuse_light(Textures::solid(V3(radiance, radiance, radiance))),
+            },
+        ])
+    }
+
+    pub fn measure_gray_card(albedo: V3, radiance: f32, samples: i32, settings: &RenderSettings) -> V3 {
+        let scene = Scene::gray_card(albedo, radiance);
+        let light_shape = Figures::xz_rect(-10.0, 10.0, -10.0, 10.0, 10.0);
+
+        (0..samples).map(|s| {
+            let ray = Ray {
+                origin: V3(0.0, 5.0, 0.0),
+                direction: V3U::from_normalized_unchecked(V3(0.0, -1.0, 0.0)),
+                time: 0.0,
+                wavelength: None,
+            };
+            let mut sampler = settings.sampler_for(s as u32, 0);
+            scene.color(ray, light_shape.clone(), 0, settings, &mut sampler)
+        }).sum::<V3>().scale(1.0 / samples as f32)
+    }
+
+    // a gray sphere on a ground sphere under the sky; small and cheap enough to render as a regression reference
+    pub fn sky_sphere() -> Scene {
+        Scene::new(vec![
+            Objects {
+                figure: Figures::sphere(V3(0.0, 0.0, -1.0), 0.5),
+                material: Materials::lambertian(Textures::solid(V3(0.5, 0.5, 0.5))),
+            },
+            Objects {
+                figure: Figures::sphere(V3(0.0, -100.5, -1.0), 100.0),
+                material: Materials::lambertian(Textures::solid(V3(0.5, 0.5, 0.5))),
+            },
+        ]).sky_background()
+    }
+
+    // a unit sphere on a neutral gray ground, lit by an overhead key light with a dim uniform fill, and a square
+    // camera framing it; for judging a material without building a scene around it
+    pub fn material_preview(material: Materials) -> (Scene, Camera) {
+        let key_light = Figures::xz_rect(-3.0, -1.0, 1.0, 3.0, 5.0);
+        let scene = Scene::new(vec![
+            Objects {
+                figure: Figures::sphere(V3(0.0, 1.0, 0.0), 1.0),
+                material: material,
+            },
+            Objects {
+                figure: Figures::xz_rect(-50.0, 50.0, -50.0, 50.0, 0.0),
+                material: Materials::lambertian(Textures::solid(V3(0.5, 0.5, 0.5))),
+            },
+            Objects {
+                figure: Figures::flip_normals(key_light.clone()),
+                material: Materials::diffuse_light(Textures::solid(V3(8.0, 8.0, 8.0))),
+            },
+        ]).with_lights(key_light).uniform_background(V3(0.2, 0.2, 0.2));
+
+        let camera = Camera::new(V3(0.0, 2.0, 6.0), V3(0.0, 1.0, 0.0), V3(0.0, 1.0, 0.0), 30.0, 1.0, 0.0, 6.0);
+        (scene, camera)
+    }
+
+    // white furnace: a unit sphere under uniform radiance; a material that neither absorbs nor creates energy
+    // should converge to the environment radiance itself
+    #[cfg(test)]
+    fn furnace(material: Materials, radiance: f32, samples: i32, settings: &RenderSettings) -> V3 {
+        let scene = Scene::new(vec![
+            Objects {
+                figure: Figures::sphere(V3(0.0, 0.0, 0.0), 1.0),
+                material: material,
+            },
+        ]).uniform_background(V3(radiance, radiance, radiance));
+
+        (0..samples).map(|s| {
+            let mut sampler = settings.sampler_for(s as u32, 0);
+            let p = V3::in_unit_disk_from(&mut sampler.rng());
+            let ray = Ray {
+                origin: V3(p.x(), p.y(), 5.0),
+                direction: V3U::from_normalized_unchecked(V3(0.0, 0.0, -1.0)),
+                time: 0.0,
+                wavelength: None,
+            };
+            scene.color(ray, Figures::Figures(vec![]), 0, settings, &mut sampler)
+        }).sum::<V3>().scale(1.0 / samples as f32)
+    }
+
+    // the standard 555-unit cornell box lit from the ceiling; the ceiling light (and the glass sphere, which focuses
+    // it) is registered as the scene's light shape
+    pub fn cornell_box(contents: CornellContents) -> Scene {
+        let light = Figures::xz_rect(213.0, 343.0, 227.0, 332.0, 554.0);
+        let mut lights = vec![light.clone()];
+        let mut objects = vec![];
+
+        objects.push(
+            Objects {
+                figure: Figures::yz_rect(0.0, 555.0, 0.0, 555.0, 555.0),
+                material: Materials::lambertian(Textures::solid(V3(0.12, 0.45, 0.15))),
+            }
+        );
+
+        objects.push(
+            Objects {
+                figure: Figures::yz_rect(0.0, 555.0, 0.0, 555.0, 0.0),
+                material: Materials::lambertian(Textures::solid(V3(0.65, 0.05, 0.05))),
+            }
+        );
+
+        objects.push(
+            Objects {
+                figure: light,
+                material: Materials::diffuse_light(Textures::solid(V3(15.0, 15.0, 15.0))),
+            }
+        );
+
+        objects.push(
+            Objects {
+                figure: Figures::xz_rect(0.0, 555.0, 0.0, 555.0, 555.0),
+                material: Materials::lambertian(Textures::solid(V3(0.73, 0.73, 0.73))),
+            }
+        );
+
+        objects.push(
+            Objects {
+                figure: Figures::xz_rect(0.0, 555.0, 0.0, 555.0, 0.0),
+                material: Materials::lambertian(Textures::solid(V3(0.73, 0.73, 0.73))),
+            }
+        );
+
+        objects.push(
+            Objects {
+                figure: Figures::xy_rect(0.0, 555.0, 0.0, 555.0, 555.0),
+                material: Materials::lambertian(Textures::solid(V3(0.73, 0.73, 0.73))),
+            }
+        );
+
+        let tall_box = Objects {
+            figure: Figures::translate(V3(265.0, 0.0, 295.0), Figures::rotate_y(15.0, Figures::cuboid(V3(0.0, 0.0, 0.0), V3(165.0, 330.0, 165.0)))),
+            material: Materials::lambertian(Textures::solid(V3(0.73, 0.73, 0.73))),
+        };
+
+        match contents {
+            CornellContents::Empty => {},
+            CornellContents::TwoBoxes => {
+                objects.push(
+                    Objects {
+                        figure: Figures::translate(V3(130.0, 0.0, 65.0), Figures::rotate_y(-18.0, Figures::cuboid(V3(0.0, 0.0, 0.0), V3(165.0, 165.0, 165.0)))),
+                        material: Materials::lambertian(Textures::solid(V3(0.73, 0.73, 0.73))),
+                    }
+                );
+                objects.push(tall_box);
+            },
+            CornellContents::GlassSphere => {
+                let sphere = Figures::sphere(V3(190.0, 90.0, 190.0), 90.0);
+                lights.push(sphere.clone());
+                objects.push(
+                    Objects {
+                        figure: sphere,
+                        material: Materials::dielectric(1.5),
+                    }
+                );
+                objects.push(tall_box);
+            },
+            CornellContents::Custom(contents) => {
+                objects.extend(contents);
+            },
+        }
+
+        Scene::new(objects).with_lights(Figures::Figures(lights))
+    }
+
+    // the light samples' half of a multiple importance sampled estimate, balance heuristic against brdf_samples
+    // samples of brdf; the BRDF samples' half is the continuation in shade, whose emission gets the complementary
+    // weight
+    fn sample_lights(&self, ray: &Ray, rec: &HitRecord, material: &Materials, brdf: &Pdfs, brdf_samples: usize, light_shape: &Figures, samples: usize, settings: &RenderSettings, sampler: &mut Samplers) -> V3 {
+        if light_shape.is_empty() {
+            return V3(0.0, 0.0, 0.0);
+        }
+
+        let plight = HitPdf::new(light_shape.clone(), rec.point);
+        // one jittered stratum of the light's parameter space per shadow ray, so the points spread over the light
+        // surface and penumbrae don't depend on how the random points happen to clump
+        let points = if samples > 1 {
+            stratified_2d(samples, &mut sampler.rng())
+        } else {
+            vec![sampler.next_2d()]
+        };
+
+        points.into_iter().map(|r| {
+            let shadow_ray = rec.spawn_ray(V3U::new(plight.generate_from(r)));
+            let pdf_val = plight.value(&shadow_ray.direction);
+            if pdf_val <= 0.0 {
+                return V3(0.0, 0.0, 0.0);
+            }
+
+            // f L / (n p_light) weighted by n p_light / (n p_light + m p_brdf)
+            let weighted_pdf = samples as f32 * pdf_val + brdf_samples as f32 * brdf.value(&shadow_ray.direction);
+            match self.hit_id_as(&shadow_ray, 0.001, std::f32::MAX, RayKind::Shadow) {
+                Some((light_rec, light_id)) => {
+                    if let Some(choices) = settings.light_choices.as_ref().filter(|_| self.objects[light_id].material.is_emissive()) {
+                        choices.record(light_id);
+                    }
+                    self.objects[light_id].material.emitted_along(&shadow_ray, &light_rec)
+                        .scale(material.scattering_pdf(ray, rec, &shadow_ray) / weighted_pdf)
+                },
+                None => V3(0.0, 0.0, 0.0),
+            }
+        }).sum::<V3>()
+    }
+
+    // how much of its on-axis radiance the light shape sends towards rec, by the emission profile of the light
+    // reached through the middle of its sampling domain; scales the share of light samples, so a point outside a
+    // focused light's beam or behind it stops aiming at the light. This only depends on the shading point, never
+    // on the sample drawn, so the mixture stays unbiased. Without focused lights it is 1 and costs nothing
+    fn light_reach(&self, light_shape: &Figures, rec: &HitRecord) -> f32 {
+        if !self.focused_lights {
+            return 1.0;
+        }
+
+        let probe = rec.spawn_ray(V3U::new(light_shape.random_from(rec.point, (0.5, 0.5))));
+        match self.hit_id_as(&probe, 0.001, std::f32::MAX, RayKind::Shadow) {
+            Some((light_rec, id)) if self.objects[id].material.is_emissive() => {
+                self.objects[id].material.emission_falloff(&probe, &light_rec)
+            },
+            _ => 1.0,
+        }
+    }
+
+    // the emissive object a ray reaches unoccluded, for the light_choices instrumentation
+    fn emitter_along(&self, ray: &Ray) -> Option<usize> {
+        self.hit_id_as(ray, 0.001, std::f32::MAX, RayKind::Shadow)
+            .map(|(_, id)| id)
+            .filter(|&id| self.objects[id].material.is_emissive())
+    }
+
+    pub fn color(&self, ray: Ray, light_shape: Figures, depth: i32, settings: &RenderSettings, sampler: &mut Samplers) -> V3 {
+        self.trace(ray, light_shape, depth, settings, sampler, &mut 0, 1.0)
+    }
+
+    // the deepest bounce of one path: 0 when the camera ray escapes, 1 when it ends at the first hit, and so on
+    pub fn path_length(&self, ray: Ray, light_shape: Figures, settings: &RenderSettings, sampler: &mut Samplers) -> i32 {
+        let mut deepest = -1;
+        self.trace(ray, light_shape, 0, settings, sampler, &mut deepest, 1.0);
+        deepest + 1
+    }
+
+    fn shade(&self, ray: &Ray, rec: HitRecord, material: &Materials, light_shape: Figures, depth: i32, settings: &RenderSettings, sampler: &mut Samplers, deepest: &mut i32, emission_weight: f32) -> V3 {
+        let scatter_rec = material.scatter(ray, &rec);
+        // media aren't sampled as lights, so their emission always counts in full
+        let emitted = material.emitted_along(ray, &rec).scale(emission_weight) + rec.emission;
+        // the ambient fill leaves diffuse surfaces like their own emission would, so every return below includes it
+        let emitted = match scatter_rec.specular_ray {
+            None if scatter_rec.is_scattered && !settings.reference_mode => emitted + scatter_rec.attenuation * settings.ambient,
+            _ => emitted,
+        };
+        if depth < settings.max_depth() && scatter_rec.is_scattered {
+            match scatter_rec.specular_ray {
+                Some(specular_ray) => {
+                    let reflected = self.trace(specular_ray, light_shape, depth + 1, settings, sampler, deepest, 1.0);
+                    emitted + settings.clamp_indirect(scatter_rec.attenuation * settings.regularize(reflected, depth), depth)
+                },
+                None if settings.light_samples > 1 && settings.use_nee => {
+                    // the shadow rays and the continuation both can find the lights; weighting the emission the
+                    // continuation hits by the balance heuristic keeps it from being counted twice
+                    // at the first hit the continuation is split over light_samples stratified directions, which
+                    // takes the low-frequency noise out of directly visible diffuse surfaces; deeper hits keep a
+                    // single one so the path count doesn't grow exponentially
+                    let continuations = if depth == 0 { settings.light_samples } else { 1 };
+                    let p = scatter_rec.pdf.unwrap();
+                    let direct = self.sample_lights(ray, &rec, material, &p, continuations, &light_shape, settings.light_samples, settings, sampler);
+                    let points = if continuations > 1 {
+                        stratified_2d(continuations, &mut sampler.rng())
+                    } else {
+                        vec![sampler.next_2d()]
+                    };
+
+                    let mut indirect = V3(0.0, 0.0, 0.0);
+                    for r in points {
+                        let scattered = rec.spawn_ray(V3U::new(p.generate_from(r)));
+                        let pdf_val = p.value(&scattered.direction);
+                        if pdf_val <= 0.0 {
+                            continue;
+                        }
+                        let light_pdf = if light_shape.is_empty() { 0.0 } else { HitPdf::new(light_shape.clone(), rec.point).value(&scattered.direction) };
+                        let brdf_pdf = continuations as f32 * pdf_val;
+                        let emission_weight = brdf_pdf / (brdf_pdf + settings.light_samples as f32 * light_pdf);
+                        indirect = indirect + self.trace(scattered.clone(), light_shape.clone(), depth + 1, settings, sampler, deepest, emission_weight)
+                            .scale(material.scattering_pdf(ray, &rec, &scattered) / pdf_val);
+                    }
+                    let indirect = indirect.scale(1.0 / continuations as f32);
+
+                    emitted + settings.clamp_indirect(scatter_rec.attenuation * settings.regularize(direct + indirect, depth), depth)
+                },
+                None => {
+                    // primary hits are always traced so that the cache grid never shows up directly
+                    let cache = settings.irradiance_cache.as_ref().filter(|_| depth > 0 && !material.is_glossy() && !settings.reference_mode);
+                    if let Some(irradiance) = cache.and_then(|c| c.lookup(&rec.point, &rec.normal)) {
+                        return emitted + settings.clamp_indirect(scatter_rec.attenuation * irradiance, depth);
+                    }
+
+                    let light_clone = light_shape.clone();
+                    let p = if light_shape.is_empty() || !settings.use_nee {
+                        scatter_rec.pdf.unwrap()
+                    } else {
+                        let fraction = settings.light_sample_fraction * self.light_reach(&light_shape, &rec);
+                        let plight = HitPdf::new(light_shape, rec.point);
+                        Pdfs::MixPdf(MixPdf::weighted(Pdfs::HitPdf(plight), scatter_rec.pdf.unwrap(), fraction))
+                    };
+                    // mixing in the environment distribution weights bright texels (a sun) by the balance heuristic
+                    let p = match self.background.pdf().filter(|_| settings.use_nee) {
+                        Some(environment) => Pdfs::MixPdf(MixPdf::new(Pdfs::EnvironmentPdf(environment), p)),
+                        None => p,
+                    };
+                    let p = match &settings.path_guide {
+                        Some(guide) => Pdfs::MixPdf(MixPdf::new(Pdfs::GuidePdf(guide.pdf(&rec.point)), p)),
+                        None => p,
+                    };
+                    let (direction, aimed) = p.generate_aimed(sampler.next_2d());
+                    let scattered = rec.spawn_ray(V3U::new(direction));
+                    let pdf_val = p.value(&scattered.direction);
+                    // a direction the mixture can't have produced would only turn into inf/NaN fireflies
+                    if pdf_val <= 0.0 {
+                        return emitted;
+                    }
+                    if let Some(choices) = settings.light_choices.as_ref().filter(|_| !light_clone.is_empty() && settings.use_nee) {
+                        if let Some(id) = self.emitter_along(&scattered) {
+                            choices.record(id);
+                        }
+                    }
+                    let kind = if aimed { RayKind::Shadow } else { RayKind::Reflection };
+                    let incoming = self.trace_as(scattered.clone(), kind, light_clone, depth + 1, settings, sampler, deepest, 1.0);
+
+                    if let Some(guide) = &settings.path_guide {
+                        guide.record(&rec.point, &scattered.direction, incoming.x() + incoming.y() + incoming.z());
+                    }
+
+                    let irradiance = incoming.scale(material.scattering_pdf(ray, &rec, &scattered) / pdf_val);
+                    if let Some(cache) = cache {
+                        cache.record(&rec.point, &rec.normal, irradiance);
+                    }
+
+                    emitted + settings.clamp_indirect(scatter_rec.attenuation * settings.regularize(irradiance, depth), depth)
+                },
+            }
+        } else {
+            emitted
+        }
+    }
+
+    // deepest tracks the largest depth at which this path hit anything
+    // emission_weight scales the emission of the surface this ray hits (not of later bounces); below 1 when the
+    // same light was also reached by shadow rays
+    fn trace(&self, ray: Ray, light_shape: Figures, depth: i32, settings: &RenderSettings, sampler: &mut Samplers, deepest: &mut i32, emission_weight: f32) -> V3 {
+        let kind = if depth == 0 { RayKind::Primary } else { RayKind::Reflection };
+        self.trace_as(ray, kind, light_shape, depth, settings, sampler, deepest, emission_weight)
+    }
+
+    // kind picks the visibility flag the ray is tested against
+    fn trace_as(&self, ray: Ray, kind: RayKind, light_shape: Figures, depth: i32, settings: &RenderSettings, sampler: &mut Samplers, deepest: &mut i32, emission_weight: f32) -> V3 {
+        match self.hit_id_as(&ray, 0.001, std::f32::MAX, kind) {
+            Some((_, id)) if depth == 0 && settings.matte_object.map_or(false, |target| target != id) => {
+                V3(0.0, 0.0, 0.0)
+            },
+            Some((rec, id)) => {
+                *deepest = (*deepest).max(depth);
+                let distance = rec.at;
+                let material = match &settings.override_material {
+                    Some(m) if !self.objects[id].material.is_emissive() => m,
+                    _ => &self.objects[id].material,
+                };
+                let min_roughness = settings.min_roughness_at(depth);
+                let roughened = if min_roughness > 0.0 { material.with_min_roughness(min_roughness) } else { None };
+                let material = roughened.as_ref().unwrap_or(material);
+                let radiance = self.shade(&ray, rec, material, light_shape, depth, settings, sampler, deepest, emission_weight);
+                self.through_atmosphere(radiance, distance)
+            },
+            None if depth == 0 && settings.matte_object.is_some() => {
+                V3(0.0, 0.0, 0.0)
+            },
+            None => {
+                self.through_atmosphere(self.background.value(&ray.direction), std::f32::INFINITY)
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Aperture {
+    Round,
+    Blades(u32),
+    Ring(f32),
+}
+
+impl Aperture {
+    fn sample_from(&self, r: (f32, f32)) -> V3 {
+        let polar = |radius: f32, phi: f32| V3(radius * phi.cos(), radius * phi.sin(), 0.0);
+
+        match self {
+            Aperture::Blades(n) if *n >= 3 => {
+                let wedge = 2.0 * std::f32::consts::PI / *n as f32;
+                let scaled = r.0 * *n as f32;
+                let k = (scaled as u32).min(*n - 1);
+                let t = scaled - k as f32;
+                let a = polar(1.0, k as f32 * wedge);
+                let b = polar(1.0, (k + 1) as f32 * wedge);
+                let radius = r.1.sqrt();
+                a.scale(radius * (1.0 - t)) + b.scale(radius * t)
+            },
+            Aperture::Ring(inner) => {
+                let inner = inner.max(0.0).min(0.99);
+                polar((inner * inner + r.1 * (1.0 - inner * inner)).sqrt(), 2.0 * std::f32::consts::PI * r.0)
+            },
+            _ => polar(r.1.sqrt(), 2.0 * std::f32::consts::PI * r.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Projection {
+    Perspective,
+    // equidistant: the distance from the image center is proportional to the angle off the view axis; holds the
+    // full field of view in radians across the image circle
+    Fisheye(f32),
+}
+
+pub struct Camera {
+    origin: V3,
+    lower_left_corner: V3,
+    horizontal: V3,
+    vertical: V3,
+    lens_radius: f32,
+    camera_pose: (V3U, V3U, V3U),
+    aperture: Aperture,
+    // aspect is the sensor (displayed) width/height; pixel_aspect is the width/height of a single pixel, so the
+    // output resolution should satisfy width * pixel_aspect / height == aspect
+    aspect: f32,
+    pixel_aspect: f32,
+    projection: Projection,
+    focus_dist: f32,
+    // rays are sent at times spread evenly over [open, close]; moving figures blur over it
+    shutter: (f32, f32),
+}
+
+impl Camera {
+    pub fn new(lookfrom: V3, lookat: V3, vup: V3, vfov: f32, aspect: f32, apertune: f32, focus_dist: f32) -> Camera {
+        let lens_radius = apertune / 2.0;
+        let theta = vfov * std::f32::consts::PI / 180.0;
+        let half_height = (theta / 2.0).tan();
+        let half_width = aspect * half_height;
+        let w = V3U::new(lookfrom - lookat);
+        let u = Camera::horizontal_axis(vup, w.as_V3());
+        // w and u are orthonormal, so their cross product has unit length already
+        let v = V3U::from_normalized_unchecked(w.as_V3().cross(u.as_V3()));
+
+        Camera {
+            origin: lookfrom,
+            lower_left_corner: lookfrom - u.scale(half_width * focus_dist) - v.scale(half_height * focus_dist) - w.scale(focus_dist),
+            horizontal: u.scale(2.0 * half_width * focus_dist),
+            vertical: v.scale(2.0 * half_height * focus_dist),
+            lens_radius: lens_radius,
+            camera_pose: (u,v,w),
+            aperture: Aperture::Round,
+            aspect: aspect,
+            pixel_aspect: 1.0,
+            projection: Projection::Perspective,
+            focus_dist: focus_dist,
+            shutter: (0.0, 0.0),
+        }
+    }
+
+    // vup parallel to the view direction (e.g. looking straight down with vup = +y) leaves no horizontal axis to
+    // speak of, and normalizing the zero cross product would fill every ray with NaNs; a world axis away from the
+    // view direction stands in for vup then
+    fn horizontal_axis(vup: V3, w: V3) -> V3U {
+        let u = vup.cross(w);
+        if u.norm() > 1e-6 * vup.norm() {
+            return V3U::new(u);
+        }
+
+        let fallback = if w.z().abs() < 0.9 { V3(0.0, 0.0, 1.0) } else { V3(0.0, 1.0, 0.0) };
+        V3U::new(fallback.cross(w))
+    }
+
+    // the same view focused at another distance; the image plane moves and scales so the framing is unchanged
+    pub fn with_focus_dist(&self, focus_dist: f32) -> Camera {
+        let ratio = focus_dist / self.focus_dist;
+        let (horizontal, vertical) = (self.horizontal.scale(ratio), self.vertical.scale(ratio));
+
+        Camera {
+            lower_left_corner: self.origin - horizontal.scale(0.5) - vertical.scale(0.5) - self.camera_pose.2.scale(focus_dist),
+            horizontal: horizontal,
+            vertical: vertical,
+            focus_dist: focus_dist,
+            ..*self
+        }
+    }
+
+    // coordinates of a world direction in the camera's basis; the basis is orthonormal, so its inverse is the
+    // transpose
+    pub fn to_camera_space(&self, direction: V3) -> V3 {
+        let (u, v, w) = self.camera_pose;
+        V3(direction.dot(u), direction.dot(v), direction.dot(w))
+    }
+
+    // a pinhole fisheye whose image circle touches the top and bottom edge of the image; fov is in degrees and
+    // may exceed 180. aspect is the image's width / height, as for Camera::new; the area around the circle
+    // renders black
+    pub fn fisheye(origin: V3, forward: V3, up: V3, fov: f32, aspect: f32) -> Camera {
+        let mut camera = Camera::new(origin, origin + forward, up, 90.0, aspect, 0.0, 1.0);
+        camera.projection = Projection::Fisheye(fov * std::f32::consts::PI / 180.0);
+        camera
+    }
+
+    // false for the corners outside a fisheye's image circle; get_ray clamps those to the circle's edge, so
+    // renderers check this and leave them black
+    pub fn in_image(&self, u: f32, v: f32) -> bool {
+        match self.projection {
+            Projection::Perspective => true,
+            Projection::Fisheye(_) => {
+                let (x, y) = ((2.0 * u - 1.0) * self.aspect, 2.0 * v - 1.0);
+                x * x + y * y <= 1.0
+            },
+        }
+    }
+
+    // looks along direction at the center of the scene bounds, backing off until the bounding sphere fits the view
+    pub fn frame_scene(scene: &Scene, direction: V3, vfov: f32, aspect: f32) -> Camera {
+        let (center, radius) = match scene.bounding_box() {
+            Some(bbox) => ((bbox.min() + bbox.max()).scale(0.5), (bbox.max() - bbox.min()).norm() * 0.5),
+            None => (V3(0.0, 0.0, 0.0), 1.0),
+        };
+
+        let half_height = (vfov * std::f32::consts::PI / 180.0 / 2.0).tan();
+        let half_fov = half_height.min(aspect * half_height).atan();
+        let distance = radius / half_fov.sin();
+        let lookfrom = center - direction.normalize().scale(distance);
+
+        Camera::new(lookfrom, center, V3(0.0, 1.0, 0.0), vfov, aspect, 0.0, distance)
+    }
+
+    // frames cameras evenly spaced on a circle of radius around target, height above it, all looking at target
+    // and focused there; the first one is on the -z side. Feed them to render_sequence for a turntable, e.g.
+    // `let mut cameras = Camera::orbit(..); render_sequence(&mut scene, frames, .., &mut |_, _| cameras.next().unwrap())`
+    pub fn orbit(target: V3, radius: f32, height: f32, frames: usize, vfov: f32, aspect: f32) -> impl Iterator<Item = Camera> {
+        (0..frames).map(move |frame| {
+            let angle = 2.0 * std::f32::consts::PI * frame as f32 / frames as f32;
+            let lookfrom = target + V3(radius * angle.sin(), height, -radius * angle.cos());
+            Camera::new(lookfrom, target, V3(0.0, 1.0, 0.0), vfov, aspect, 0.0, (lookfrom - target).norm())
+        })
+    }
+
+    // non-square pixels, e.g. anamorphic output; the sensor itself (and so the field of view) is unchanged
+    pub fn with_pixel_aspect(mut self, pixel_aspect: f32) -> Camera {
+        self.pixel_aspect = pixel_aspect;
+        self
+    }
+
+    // stratified pixel jitter matched to this camera's pixel shape, for renders of `samples` passes
+    pub fn stratified_sampler(&self, samples: u32) -> SamplerKind {
+        SamplerKind::Stratified {
+            samples: samples,
+            pixel_aspect: self.pixel_aspect,
+        }
+    }
+
+    pub fn matches_resolution(&self, width: i32, height: i32) -> bool {
+        let displayed = width as f32 * self.pixel_aspect / height as f32;
+        (displayed - self.aspect).abs() <= 1e-2 * self.aspect
+    }
+
+    // motion blur: the shutter stays open from open to close, in the time of Figures::rotate_y_motion
+    pub fn with_shutter(mut self, open: f32, close: f32) -> Camera {
+        self.shutter = (open, close);
+        self
+    }
+
+    pub fn with_aperture(mut self, aperture: Aperture) -> Camera {
+        self.aperture = aperture;
+        self
+    }
+
+    pub fn with_aperture_blades(self, blades: u32) -> Camera {
+        self.with_aperture(Aperture::Blades(blades))
+    }
+
+    pub fn with_aperture_ring(self, inner_ratio: f32) -> Camera {
+        self.with_aperture(Aperture::Ring(inner_ratio))
+    }
+
+    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
+        self.get_ray_sampled(u, v, &mut Samplers::Random(RandomSampler::new(with_stream(|rng| rng.gen::<u64>()))))
+    }
+
+    pub fn get_ray_sampled(&self, u: f32, v: f32, sampler: &mut Samplers) -> Ray {
+        let (lens_u, lens_v) = sampler.next_2d();
+        self.get_ray_with_lens(u, v, lens_u, lens_v)
+    }
+
+    // lens_u and lens_v in [0,1) pick a point on the aperture, mapped through its shape
+    pub fn get_ray_with_lens(&self, u: f32, v: f32, lens_u: f32, lens_v: f32) -> Ray {
+        let (open, close) = self.shutter;
+        let time = if close > open { open + random() * (close - open) } else { open };
+
+        if let Projection::Fisheye(fov) = self.projection {
+            let (x, y) = ((2.0 * u - 1.0) * self.aspect, 2.0 * v - 1.0);
+            let theta = (x * x + y * y).sqrt().min(1.0) * fov / 2.0;
+            let phi = y.atan2(x);
+            let (u_axis, v_axis, w_axis) = self.camera_pose;
+            let direction = u_axis.scale(theta.sin() * phi.cos()) + v_axis.scale(theta.sin() * phi.sin()) - w_axis.scale(theta.cos());
+
+            return Ray {
+                origin: self.origin,
+                direction: V3U::from_normalized_unchecked(direction),
+                time: time,
+                wavelength: None,
+            };
+        }
+
+        let rd = self.aperture.sample_from((lens_u, lens_v)).scale(self.lens_radius);
+        let offset = self.camera_pose.0.scale(rd.x()) + self.camera_pose.1.scale(rd.y());
+
+        Ray {
+            origin: self.origin + offset,
+            direction: V3U::new(self.lower_left_corner + self.horizontal.scale(u) + self.vertical.scale(v) - self.origin - offset),
+            time: time,
+            wavelength: None,
+        }
+    }
+}
+
+// the same seed gives the same spheres
+pub fn create_random_scene(seed: u32) -> Scene {
+    let mut rng = seeded_rng(u64::from(seed));
+    let mut objects = vec![];
+    objects.push(
+        Objects {
+            figure: Figures::sphere(V3(0.0, -1000.0, 0.0), 1000.0),
+            material: Materials::lambertian(Textures::solid(V3(0.5, 0.5, 0.5))),
+        }
+    );
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let material = rng.gen::<f32>();
+            let center = V3(
+                a as f32 + 0.9 * rng.gen::<f32>(),
+                0.2,
+                b as f32 + 0.9 * rng.gen::<f32>(),
+            );
+
+            if (center - V3(4.0, 0.2, 0.0)).norm() > 0.9 {
+                if material < 0.8 {
+                    objects.push(
+                        Objects {
+                            figure: Figures::sphere(center, 0.2),
+                            material: Materials::lambertian(Textures::solid(V3(
+                                rng.gen::<f32>() * rng.gen::<f32>(),
+                                rng.gen::<f32>() * rng.gen::<f32>(),
+                                rng.gen::<f32>() * rng.gen::<f32>(),
+                            )))
+                        }
+                    );
+                } else if material < 0.95 {
+                    objects.push(
+                        Objects {
+                            figure: Figures::sphere(center, 0.2),
+                            material: Materials::metal(V3(
+                                0.5 * (1.0 + rng.gen::<f32>()),
+                                0.5 * (1.0 + rng.gen::<f32>()),
+                                0.5 * (1.0 + rng.gen::<f32>()),
+                            )
+                            , 0.5 * rng.gen::<f32>())
+                        }
+                    );
+                } else {
+                    objects.push(
+                        Objects {
+                            figure: Figures::sphere(center, 0.2),
+                            material: Materials::dielectric(1.5),
+                        }
+                    );
+                }
+            }
+        }
+    }
+
+    objects.push(
+        Objects {
+            figure: Figures::sphere(V3(0.0, 1.0, 0.0), 1.0),
+            material: Materials::dielectric(1.5),
+        }
+    );
+    objects.push(
+        Objects {
+            figure: Figures::sphere(V3(-4.0, 1.0, 0.0), 1.0),
+            material: Materials::lambertian(Textures::solid(V3(0.4, 0.2, 0.1))),
+        }
+    );
+    objects.push(
+        Objects {
+            figure: Figures::sphere(V3(4.0, 1.0, 0.0), 1.0),
+            material: Materials::metal(V3(0.7, 0.6, 0.5), 0.0),
+        }
+    );
+
+    Scene::new(objects)
+}
+
+pub fn create_nextweek_scene(seed: u32) -> Scene {
+    let mut rng = seeded_rng(u64::from(seed));
+    let nb = 20;
+    let mut objects = vec![];
+
+    objects.push(
+        Objects {
+            figure: Figures::bvh_node(
+                (0..nb).flat_map(|i| (0..nb).map(move |j| (i, j))).map(|(i, j)| {
+                    let w = 100.0;
+
+                    Figures::cuboid(
+                        V3(
+                            -1000.0 + i as f32 * w,
+                            0.0,
+                            -1000.0 + j as f32 * w,
+                        ),
+                        V3(
+                            -1000.0 + i as f32 * w + w,
+                            100.0 * (rng.gen::<f32>() + 0.01),
+                            -1000.0 + j as f32 * w + w,
+                        )
+                    )
+                }).collect(),
+                0.0,
+                1.0,
+            ),
+            material: Materials::lambertian(
+                Textures::solid(V3(0.48, 0.83, 0.53))
+            ),
+        }
+    );
+
+    objects.push(
+        Objects {
+            figure: Figures::xz_rect(123.0, 423.0, 147.0, 412.0, 554.0),
+            material: Materials::diffuse_light(Textures::solid(V3(7.0, 7.0, 7.0))),
+        }
+    );
+
+    objects.push(
+        Objects {
+            figure: Figures::sphere(V3(400.0, 400.0, 200.0), 50.0),
+            material: Materials::lambertian(Textures::solid(V3(0.7, 0.3, 0.1))),
+        }
+    );
+
+    objects.push(
+        Objects {
+            figure: Figures::sphere(V3(260.0, 150.0, 45.0), 50.0),
+            material: Materials::dielectric(1.5),
+        }
+    );
+
+    objects.push(
+        Objects {
+            figure: Figures::sphere(V3(0.0, 150.0, 145.0), 50.0),
+            material: Materials::metal(V3(0.8, 0.8, 0.9), 10.0),
+        }
+    );
+
+    objects.push(
+        Objects {
+            figure: Figures::sphere(V3(360.0, 150.0, 145.0), 70.0),
+            material: Materials::dielectric(1.5),
+        }
+    );
+
+    objects.push(
+        Objects {
+            figure: Figures::constant_medium(0.2, Figures::sphere(V3(360.0, 150.0, 145.0), 70.0)),
+            material: Materials::isotropic(Textures::solid(V3(0.2, 0.4, 0.9))),
+        }
+    );
+
+    objects.push(
+        Objects {
+            figure: Figures::sphere(V3(0.0, 0.0, 0.0), 5000.0),
+            material: Materials::dielectric(1.5),
+        }
+    );
+
+    objects.push(
+        Objects {
+            figure: Figures::constant_medium(0.0001, Figures::sphere(V3(0.0, 0.0, 0.0), 5000.0)),
+            material: Materials::isotropic(Textures::solid(V3(1.0, 1.0, 1.0))),
+        }
+    );
+
+    objects.push(
+        Objects {
+            figure: Figures::sphere(V3(400.0, 200.0, 400.0), 100.0),
+            material: Materials::lambertian(Textures::solid(V3(0.1, 0.4, 0.8))),
+        }
+    );
+
+    objects.push(
+        Objects {
+            figure: Figures::sphere(V3(220.0, 280.0, 300.0), 80.0),
+            material: Materials::lambertian(Textures::noise(0.1)),
+        }
+    );
+
+    let ns = 1000;
+    objects.push(
+        Objects {
+            figure: Figures::translate(
+                V3(-100.0, 270.0, 395.0),
+                Figures::rotate_y(
+                    15.0,
+                    Figures::bvh_node(
+                        (0..ns).map(|_| {
+                            Figures::sphere(V3(
+                                165.0 * rng.gen::<f32>(),
+                                165.0 * rng.gen::<f32>(),
+                                165.0 * rng.gen::<f32>(),
+                            ), 10.0)
+                        }).collect(),
+                        0.0,
+                        1.0
+                    )
+                )
+            ),
+            material: Materials::lambertian(Textures::solid(V3(0.73, 0.73, 0.73)))
+        }
+    );
+
+    Scene::new(objects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ray(origin: V3, direction: V3) -> Ray {
+        Ray { origin: origin, direction: V3U::new(direction), time: 0.0, wavelength: None }
+    }
+
+    // the per-pixel means after the last pass
+    fn render_means(scene: &Scene, camera: &Camera, settings: &RenderSettings, name: &str) -> Vec<[f32; 3]> {
+        let path = std::env::temp_dir().join(format!("ruyt-test-{}.ppm", name));
+        let mut means = vec![];
+        scene_renderer(scene, camera, settings).render_progressive(path.to_str().unwrap(), settings, &mut |frame, _| {
+            means = frame.means().iter().map(|m| m.as_array()).collect();
+        });
+        means
+    }
+
+    fn cornell_camera() -> Camera {
+        Camera::new(V3(278.0, 278.0, -800.0), V3(278.0, 278.0, 0.0), V3(0.0, 1.0, 0.0), 40.0, 1.0, 0.0, 10.0)
+    }
+
+    #[test]
+    fn regularization_widens_with_depth() {
+        let settings = RenderSettings { regularization_depth: Some(1), ..RenderSettings::default() };
+        let bright = V3(100.0, 100.0, 0.5);
+        assert!(settings.regularize(bright, 0).as_array() == bright.as_array());
+        assert!(settings.regularize(bright, 1).as_array() == [REGULARIZATION_LIMIT, REGULARIZATION_LIMIT, 0.5]);
+        assert!(settings.regularize(bright, 2).as_array() == [2.0 * REGULARIZATION_LIMIT, 2.0 * REGULARIZATION_LIMIT, 0.5]);
+
+        // mirrors past the regularization depth become rough
+        assert!(settings.min_roughness_at(0) == 0.0 && settings.min_roughness_at(1) == REGULARIZATION_ROUGHNESS);
+        let reference = RenderSettings { reference_mode: true, ..settings };
+        assert!(reference.min_roughness_at(3) == 0.0 && reference.regularize(bright, 3).as_array() == bright.as_array());
+    }
+
+    // every ray of a spectral render, spawned ones included, carries a wavelength of the visible range
+    struct WavelengthProbe {
+        seen: std::sync::Mutex<Vec<Option<f32>>>,
+    }
+
+    impl Hit for WavelengthProbe {
+        fn hit(&self, ray: &Ray, _tmin: f32, _tmax: f32) -> Option<HitRecord> {
+            self.seen.lock().unwrap().push(ray.wavelength);
+            None
+        }
+
+        fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<Aabb> {
+            None
+        }
+    }
+
+    #[test]
+    fn spectral_rays_carry_their_wavelength() {
+        let probe = std::sync::Arc::new(WavelengthProbe { seen: std::sync::Mutex::new(vec![]) });
+        let gray = || Materials::lambertian(Textures::solid(V3(0.5, 0.5, 0.5)));
+        let scene = Scene::new(vec![
+            Objects { figure: Figures::xz_rect(-50.0, 50.0, -50.0, 50.0, 0.0), material: gray() },
+            Objects { figure: Figures::Custom(probe.clone()), material: gray() },
+        ]).uniform_background(V3(1.0, 1.0, 1.0));
+        let camera = Camera::new(V3(0.0, 2.0, 4.0), V3(0.0, 0.0, 0.0), V3(0.0, 1.0, 0.0), 40.0, 1.0, 0.0, 4.0);
+        let settings = RenderSettings { width: 4, height: 4, samples_per_pixel: 2, ..RenderSettings::default() };
+        render_spectral(&scene, &camera, &settings, std::env::temp_dir().join("ruyt-test-spectral-probe.ppm").to_str().unwrap());
+
+        let seen = probe.seen.lock().unwrap();
+        assert!(seen.len() > (settings.width * settings.height * settings.samples_per_pixel * settings.spectral_samples) as usize);
+        assert!(seen.iter().all(|nm| nm.map_or(false, |nm| (LAMBDA_MIN..=LAMBDA_MAX).contains(&nm))));
+    }
+
+    // the wavelength offset comes from the pixel's sampler, not from whatever state the shared stream was left in
+    #[test]
+    fn spectral_renders_repeat_for_a_seed() {
+        let scene = Scene::cornell_box(CornellContents::GlassSphere);
+        let settings = RenderSettings { width: 6, height: 6, samples_per_pixel: 2, seed: 5, ..RenderSettings::default() };
+        let render_once = |name: &str| {
+            let path = std::env::temp_dir().join(format!("ruyt-test-spectral-{}.ppm", name));
+            render_spectral(&scene, &cornell_camera(), &settings, path.to_str().unwrap());
+            std::fs::read(path).unwrap()
+        };
+
+        let a = render_once("a");
+        reseed_stream(12345);
+        random();
+        let b = render_once("b");
+        assert!(a == b);
+    }
+
+    // a miss has crossed an infinite stretch of fog, a near hit hardly any
+    #[test]
+    fn atmosphere_covers_the_background() {
+        let scene = Scene::new(vec![Objects {
+            figure: Figures::xy_rect(-1.0, 1.0, -1.0, 1.0, 0.0),
+            material: Materials::diffuse_light(Textures::solid(V3(1.0, 1.0, 1.0))),
+        }]).uniform_background(V3(1.0, 1.0, 1.0)).with_atmosphere(0.01, V3(0.2, 0.3, 0.4));
+        let settings = RenderSettings::default();
+        let mut sampler = settings.sampler_for(0, 0);
+
+        let sky = scene.color(ray(V3(5.0, 0.0, 1.0), V3(0.0, 0.0, -1.0)), Figures::Figures(vec![]), 0, &settings, &mut sampler);
+        assert!(sky.as_array() == [0.2, 0.3, 0.4]);
+        let near = scene.color(ray(V3(0.0, 0.0, 1.0), V3(0.0, 0.0, -1.0)), Figures::Figures(vec![]), 0, &settings, &mut sampler);
+        assert!(near.as_array().iter().all(|&c| c > 0.99));
+    }
+
+    // each distance gives its own file, and refocusing a wide aperture changes the image
+    #[test]
+    fn focus_bracket_renders_every_distance() {
+        let mut scene = Scene::cornell_box(CornellContents::GlassSphere);
+        let camera = Camera::new(V3(278.0, 278.0, -800.0), V3(278.0, 278.0, 0.0), V3(0.0, 1.0, 0.0), 40.0, 1.0, 200.0, 1000.0);
+        let settings = RenderSettings { width: 8, height: 8, samples_per_pixel: 4, seed: 4, ..RenderSettings::default() };
+        let pattern = std::env::temp_dir().join("ruyt-test-focus-{}.ppm");
+        let reports = focus_bracket(&mut scene, &camera, &[100.0, 1000.0], &settings, pattern.to_str().unwrap());
+        assert!(reports.len() == 2);
+
+        let read = |index: usize| std::fs::read(pattern.to_str().unwrap().replace("{}", &index.to_string())).unwrap();
+        assert!(read(0) != read(1));
+    }
+
+    #[test]
+    fn renders_repeat_for_a_seed() {
+        let scene = Scene::cornell_box(CornellContents::GlassSphere);
+        let settings = |seed| RenderSettings { width: 12, height: 12, samples_per_pixel: 4, seed: seed, ..RenderSettings::default() };
+
+        let a = render_means(&scene, &cornell_camera(), &settings(7), "seed-a");
+        let b = render_means(&scene, &cornell_camera(), &settings(7), "seed-b");
+        let c = render_means(&scene, &cornell_camera(), &settings(8), "seed-c");
+        assert!(a == b);
+        assert!(a != c);
+    }
+
+    #[test]
+    fn scene_generators_follow_their_seed() {
+        let camera = Camera::new(V3(13.0, 2.0, 3.0), V3(0.0, 0.0, 0.0), V3(0.0, 1.0, 0.0), 20.0, 1.0, 0.0, 10.0);
+        let settings = RenderSettings { width: 8, height: 8, samples_per_pixel: 2, ..RenderSettings::default() };
+
+        let a = render_means(&create_random_scene(1).sky_background(), &camera, &settings, "random-a");
+        let b = render_means(&create_random_scene(1).sky_background(), &camera, &settings, "random-b");
+        let c = render_means(&create_random_scene(2).sky_background(), &camera, &settings, "random-c");
+        assert!(a == b);
+        assert!(a != c);
+    }
+
+    // a fixed-seed render of the sky reference scene, byte for byte; regenerate testdata/sky_sphere_16.ppm only
+    // for an intended change to the image
+    #[test]
+    fn sky_sphere_matches_golden() {
+        let camera = Camera::new(V3(0.0, 0.0, 0.0), V3(0.0, 0.0, -1.0), V3(0.0, 1.0, 0.0), 90.0, 1.0, 0.0, 1.0);
+        let settings = RenderSettings { width: 16, height: 16, samples_per_pixel: 8, seed: 1, ..RenderSettings::default() };
+        let path = std::env::temp_dir().join("ruyt-test-sky-sphere.ppm");
+        render(&Scene::sky_sphere(), &camera, &settings, path.to_str().unwrap());
+
+        let golden: &[u8] = include_bytes!("../testdata/sky_sphere_16.ppm");
+        assert!(std::fs::read(&path).unwrap() == golden, "render differs from testdata/sky_sphere_16.ppm");
+    }
+
+    fn assert_furnace(material: Materials) {
+        let settings = RenderSettings { seed: 3, ..RenderSettings::default() };
+        let color = Scene::furnace(material, 1.0, 4000, &settings);
+        for &c in color.as_array().iter() {
+            assert!((c - 1.0).abs() < 0.03, "furnace gave {}", c);
+        }
+    }
+
+    #[test]
+    fn lambertian_passes_furnace() {
+        assert_furnace(Materials::lambertian(Textures::solid(V3(1.0, 1.0, 1.0))));
+    }
+
+    #[test]
+    fn metal_passes_furnace() {
+        assert_furnace(Materials::metal(V3(1.0, 1.0, 1.0), 0.0));
+    }
+
+    #[test]
+    fn dielectric_passes_furnace() {
+        assert_furnace(Materials::dielectric(1.5));
+    }
+
+    // light samples taken from a floor in the light's own plane are edge-on and have no valid density
+    #[test]
+    fn coplanar_rect_light_gives_no_nan() {
+        let scene = Scene::new(vec![Objects {
+            figure: Figures::xz_rect(-5.0, 5.0, -5.0, 5.0, 0.0),
+            material: Materials::lambertian(Textures::solid(V3(0.5, 0.5, 0.5))),
+        }]).uniform_background(V3(1.0, 1.0, 1.0));
+        let light_shape = Figures::xz_rect(6.0, 7.0, -1.0, 1.0, 0.0);
+        let settings = RenderSettings { seed: 2, ..RenderSettings::default() };
+
+        let samples = 4000;
+        let mut sum = V3(0.0, 0.0, 0.0);
+        for s in 0..samples {
+            let mut sampler = settings.sampler_for(s, 0);
+            let color = scene.color(ray(V3(0.0, 5.0, 0.0), V3(0.0, -1.0, 0.0)), light_shape.clone(), 0, &settings, &mut sampler);
+            assert!(color.as_array().iter().all(|c| c.is_finite()));
+            sum = sum + color;
+        }
+        assert!((sum.x() / samples as f32 - 0.5).abs() < 0.03);
+    }
+
+    #[test]
+    fn top_down_camera_basis_is_orthonormal() {
+        let views = [
+            (V3(0.0, 10.0, 0.0), V3(0.0, 0.0, 0.0), V3(0.0, 1.0, 0.0)),
+            (V3(0.0, -10.0, 0.0), V3(0.0, 0.0, 0.0), V3(0.0, 1.0, 0.0)),
+            (V3(0.0, 0.0, 10.0), V3(0.0, 0.0, 0.0), V3(0.0, 0.0, 1.0)),
+            (V3(0.0, 10.0, 0.0), V3(0.0, 0.0, 0.0), V3(0.0, 0.0, 0.0)),
+        ];
+        for &(lookfrom, lookat, vup) in views.iter() {
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, 1.5, 0.1, 10.0);
+            let (u, v, w) = camera.camera_pose;
+            for axis in [u.as_V3(), v.as_V3(), w.as_V3()].iter() {
+                assert!(axis.as_array().iter().all(|c| c.is_finite()));
+                assert!((axis.norm() - 1.0).abs() < 1e-5);
+            }
+            assert!(u.dot(v).abs() < 1e-5 && v.dot(w).abs() < 1e-5 && w.dot(u).abs() < 1e-5);
+
+            let r = camera.get_ray_with_lens(0.3, 0.7, 0.5, 0.5);
+            assert!(r.direction.as_V3().as_array().iter().all(|c| c.is_finite()));
+        }
+    }
+
+    // a glowing arm spinning a quarter turn: with the shutter open it smears into partly covered pixels along
+    // the arc, closed it is a sharp bar
+    #[test]
+    fn spinning_box_smears_over_the_shutter() {
+        let scene = Scene::new(vec![Objects {
+            figure: Figures::rotate_y_motion(0.0, 90.0, Figures::cuboid(V3(0.5, -0.2, -0.2), V3(2.0, 0.2, 0.2))),
+            material: Materials::diffuse_light(Textures::solid(V3(1.0, 1.0, 1.0))),
+        }]);
+        let camera = || Camera::new(V3(0.0, 8.0, 0.0), V3(0.0, 0.0, 0.0), V3(0.0, 0.0, -1.0), 40.0, 1.0, 0.0, 8.0);
+        let settings = RenderSettings { width: 24, height: 24, samples_per_pixel: 16, gamma: GammaMode::None, ..RenderSettings::default() };
+
+        let partial = |means: Vec<[f32; 3]>| means.iter().filter(|m| m[0] > 0.1 && m[0] < 0.9).count();
+        let sharp = partial(render_means(&scene, &camera(), &settings, "spin-sharp"));
+        let blurred = partial(render_means(&scene, &camera().with_shutter(0.0, 1.0), &settings, "spin-blurred"));
+        assert!(blurred > 2 * sharp.max(1), "{} {}", sharp, blurred);
+    }
+
+    // a wide fisheye under a bright sky: the circle is lit, the corners outside it stay black rather than
+    // repeating the rim
+    #[test]
+    fn fisheye_leaves_the_outside_of_its_circle_black() {
+        let scene = Scene::new(vec![]).uniform_background(V3(1.0, 1.0, 1.0));
+        let camera = Camera::fisheye(V3(0.0, 0.0, 0.0), V3(0.0, 0.0, -1.0), V3(0.0, 1.0, 0.0), 180.0, 2.0);
+        let settings = RenderSettings { width: 32, height: 16, samples_per_pixel: 4, ..RenderSettings::default() };
+
+        let means = render_means(&scene, &camera, &settings, "fisheye");
+        let at = |i: usize, j: usize| means[j * 32 + i][0];
+        assert!(at(16, 8) > 0.99 && at(9, 8) > 0.99 && at(22, 8) > 0.99);
+        assert!(at(0, 0) == 0.0 && at(31, 15) == 0.0 && at(2, 8) == 0.0 && at(29, 8) == 0.0);
+    }
+
+    // a card between the floor and the light that is hidden from shadow rays must not block the bounces the
+    // mixture aims at the light
+    #[test]
+    fn light_aimed_bounces_use_the_shadow_flag() {
+        let light = Figures::xz_rect(-1.0, 1.0, -1.0, 1.0, 5.0);
+        let floor_radiance = |shadow: bool| {
+            let scene = Scene::new(vec![
+                Objects { figure: light.clone(), material: Materials::diffuse_light(Textures::solid(V3(4.0, 4.0, 4.0))) },
+                Objects {
+                    figure: Figures::xz_rect(-10.0, 10.0, -10.0, 10.0, 0.0),
+                    material: Materials::lambertian(Textures::solid(V3(0.5, 0.5, 0.5))),
+                },
+                Objects {
+                    figure: Figures::xz_rect(-2.0, 2.0, -2.0, 2.0, 2.5),
+                    material: Materials::lambertian(Textures::solid(V3(0.0, 0.0, 0.0))),
+                },
+            ]).with_lights(light.clone()).with_visibility(2, true, shadow, true);
+            let settings = RenderSettings { seed: 4, ..RenderSettings::default() };
+
+            (0..2000).map(|s| {
+                let mut sampler = settings.sampler_for(s, 0);
+                scene.color(ray(V3(0.0, 1.0, 0.0), V3(0.0, -1.0, 0.0)), scene.lights().clone(), 0, &settings, &mut sampler).x()
+            }).sum::<f32>() / 2000.0
+        };
+
+        let (blocked, unblocked) = (floor_radiance(true), floor_radiance(false));
+        assert!(blocked < 0.01);
+        assert!(unblocked > 0.02, "{}", unblocked);
+    }
+
+    // weighting the light samples by a focused light's profile must not change what the floor converges to
+    #[test]
+    fn focused_light_sampling_stays_unbiased() {
+        let light = Figures::flip_normals(Figures::xz_rect(-1.0, 1.0, -1.0, 1.0, 2.0));
+        let scene = Scene::new(vec![
+            Objects {
+                figure: light.clone(),
+                material: Materials::diffuse_light_with(Textures::solid(V3(4.0, 4.0, 4.0)), EmissionProfile::Focused(4.0)),
+            },
+            Objects {
+                figure: Figures::xz_rect(-10.0, 10.0, -10.0, 10.0, 0.0),
+                material: Materials::lambertian(Textures::solid(V3(0.5, 0.5, 0.5))),
+            },
+        ]).with_lights(light);
+
+        let floor_radiance = |x: f32, use_nee: bool| {
+            let settings = RenderSettings { seed: 5, use_nee: use_nee, ..RenderSettings::default() };
+            (0..20000).map(|s| {
+                let mut sampler = settings.sampler_for(s, 0);
+                scene.color(ray(V3(x, 1.0, 0.0), V3(0.0, -1.0, 0.0)), scene.lights().clone(), 0, &settings, &mut sampler).x()
+            }).sum::<f32>() / 20000.0
+        };
+
+        for &x in [0.0, 1.5].iter() {
+            let (mixed, brdf_only) = (floor_radiance(x, true), floor_radiance(x, false));
+            assert!((mixed - brdf_only).abs() < 0.08 * brdf_only, "{} {} {}", x, mixed, brdf_only);
+        }
+    }
+
+    // closest hit by testing every object, to check the object BVH against
+    fn hit_linear(scene: &Scene, ray: &Ray) -> Option<(f32, usize)> {
+        scene.objects.iter().enumerate()
+            .filter_map(|(id, object)| object.figure.hit(ray, 0.001, std::f32::MAX).map(|rec| (rec.at, id)))
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+    }
+
+    #[test]
+    fn hit_statistics_count_closest_hits() {
+        let gray = || Materials::lambertian(Textures::solid(V3(0.5, 0.5, 0.5)));
+        let scene = Scene::new(vec![
+            Objects { figure: Figures::sphere(V3(0.0, 0.0, 0.0), 1.0), material: gray() },
+            Objects { figure: Figures::sphere(V3(0.0, 0.0, -5.0), 1.0), material: gray() },
+        ]);
+        assert!(scene.hit_statistics().is_none());
+
+        // the near sphere hides the far one from the first two rays
+        let scene = scene.with_hit_statistics();
+        for &origin in [V3(0.0, 0.0, 5.0), V3(0.5, 0.0, 5.0), V3(0.0, 3.0, 5.0)].iter() {
+            scene.hit(&ray(origin, V3(0.0, 0.0, -1.0)), 0.001, std::f32::MAX);
+        }
+        scene.hit(&ray(V3(0.0, 0.0, -10.0), V3(0.0, 0.0, 1.0)), 0.001, std::f32::MAX);
+        assert!(scene.hit_statistics() == Some(vec![(0, 2), (1, 1)]));
+    }
+
+    #[test]
+    fn object_bvh_matches_linear_scan() {
+        // translated and rotated figures, whose boxes the BVH relies on; no media, since their hits are random
+        let mut rng = seeded_rng(5);
+        let gray = || Materials::lambertian(Textures::solid(V3(0.5, 0.5, 0.5)));
+        let transformed = (0..60).map(|i| {
+            let offset = V3::in_unit_sphere_from(&mut rng).scale(500.0);
+            let figure = if i % 2 == 0 {
+                Figures::sphere(V3(0.0, 0.0, 0.0), 20.0)
+            } else {
+                Figures::rotate_y(37.0 * i as f32, Figures::cuboid(V3(-10.0, -10.0, -10.0), V3(30.0, 10.0, 5.0)))
+            };
+            Objects { figure: Figures::translate(offset, figure), material: gray() }
+        }).collect();
+
+        for scene in vec![Scene::new(transformed), create_random_scene(3)] {
+            let mut sampler = Samplers::new(SamplerKind::Random, 0, 11);
+            let center = scene.bounding_box().map_or(V3(0.0, 0.0, 0.0), |b| (b.min() + b.max()).scale(0.5));
+            for _ in 0..2000 {
+                let mut rng = sampler.rng();
+                let origin = center + V3::in_unit_sphere_from(&mut rng).scale(600.0);
+                let direction = V3::in_unit_sphere_from(&mut rng);
+                if direction.norm() < 1e-3 {
+                    continue;
+                }
+
+                let r = ray(origin, direction);
+                let bvh = scene.hit_id(&r, 0.001, std::f32::MAX).map(|(rec, id)| (rec.at, id));
+                let linear = hit_linear(&scene, &r);
+                match (bvh, linear) {
+                    (Some((t0, id0)), Some((t1, id1))) => assert!(id0 == id1 || (t0 - t1).abs() < 1e-3, "{} {} {} {}", id0, id1, t0, t1),
+                    (None, None) => {},
+                    _ => panic!("bvh and linear scan disagree"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn scene_hits_translated_object() {
+        let scene = Scene::new(vec![Objects {
+            figure: Figures::translate(V3(100.0, 0.0, 0.0), Figures::sphere(V3(0.0, 0.0, 0.0), 1.0)),
+            material: Materials::lambertian(Textures::solid(V3(0.5, 0.5, 0.5))),
+        }]);
+
+        let bbox = scene.bounding_box().unwrap();
+        assert!((bbox.min().x() - 99.0).abs() < 1e-3 && (bbox.max().x() - 101.0).abs() < 1e-3);
+
+        let (rec, _) = scene.hit(&ray(V3(100.0, 0.0, -10.0), V3(0.0, 0.0, 1.0)), 0.001, std::f32::MAX).unwrap();
+        assert!((rec.at - 9.0).abs() < 1e-3);
+        assert!(scene.hit(&ray(V3(0.0, 0.0, -10.0), V3(0.0, 0.0, 1.0)), 0.001, std::f32::MAX).is_none());
+    }
+}
@@ -0,0 +1,43 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use crate::vector::*;
+
+// debug instrumentation for light sampling: counts which emissive object each light-sampled direction reached,
+// until the counts are taken. Only filled while set in RenderSettings::light_choices
+#[derive(Clone, Default)]
+pub struct LightChoices {
+    counts: RefCell<HashMap<usize, u32>>,
+}
+
+impl LightChoices {
+    pub fn new() -> LightChoices {
+        LightChoices {
+            counts: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, object: usize) {
+        *self.counts.borrow_mut().entry(object).or_insert(0) += 1;
+    }
+
+    // (object id, times reached) since the last take, most reached first
+    pub fn take(&self) -> Vec<(usize, u32)> {
+        let mut counts = self.counts.borrow_mut().drain().collect::<Vec<(usize, u32)>>();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        counts
+    }
+
+    // a saturated color per object id, with neighbouring ids far apart in hue
+    pub fn color(object: usize) -> V3 {
+        let hue = (object as f32 * 0.618_034).fract() * 6.0;
+        let x = 1.0 - (hue % 2.0 - 1.0).abs();
+        match hue as i32 {
+            0 => V3(1.0, x, 0.0),
+            1 => V3(x, 1.0, 0.0),
+            2 => V3(0.0, 1.0, x),
+            3 => V3(0.0, x, 1.0),
+            4 => V3(x, 0.0, 1.0),
+            _ => V3(1.0, 0.0, x),
+        }
+    }
+}
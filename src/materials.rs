@@ -1,6 +1,9 @@
 use crate::vector::*;
 use crate::textures::*;
 use crate::pdf::*;
+use crate::figures::*;
+use crate::spectrum::*;
+use crate::sampler::*;
 
 #[derive(Clone)]
 pub struct HitRecord {
@@ -9,6 +12,39 @@ pub struct HitRecord {
     pub normal: V3,
     pub u: f32,
     pub v: f32,
+    pub front_face: bool,
+    // what a participating medium emits towards the ray at this collision; the expected sum over collisions is
+    // the emission integrated along the ray, attenuated by the medium
+    pub emission: V3,
+    // the vertex color interpolated over a colored triangle, which diffuse materials use in place of their albedo
+    pub color: Option<V3>,
+    // the time and wavelength of the ray that made the hit, which the rays spawned from it keep
+    pub time: f32,
+    pub wavelength: Option<f32>,
+}
+
+// relative to the magnitude of the hit point, since the spacing between f32 values grows with it
+const SPAWN_OFFSET: f32 = 1e-4;
+
+impl HitRecord {
+    // nudges the origin off the surface, to the side the new ray leaves through, so it can't hit the surface
+    // again; a fixed t_min alone fails on huge spheres and on scaled instances
+    pub fn spawn_ray(&self, direction: V3U) -> Ray {
+        let p = self.point;
+        let scale = 1.0 + p.x().abs().max(p.y().abs()).max(p.z().abs());
+        let side = if direction.dot(self.normal) < 0.0 { -1.0 } else { 1.0 };
+        Ray {
+            origin: p + self.normal.scale(side * SPAWN_OFFSET * scale),
+            direction: direction,
+            time: self.time,
+            wavelength: self.wavelength,
+        }
+    }
+
+    pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: V3U) {
+        self.front_face = ray.direction.dot(outward_normal) < 0.0;
+        self.normal = if self.front_face { outward_normal.as_V3() } else { -outward_normal.as_V3() };
+    }
 }
 
 trait Material {
@@ -38,7 +74,7 @@ pub struct Lambertian {
 impl Material for Lambertian {
     fn scatter(&self, _ray_in: &Ray, rec: &HitRecord) -> ScatterRecord {
         ScatterRecord {
-            attenuation: self.albedo.value(rec.u, rec.v, &rec.point),
+            attenuation: rec.color.unwrap_or_else(|| self.albedo.value(rec.u, rec.v, &rec.point)),
             specular_ray: None,
             pdf: Some(Pdfs::CosinePdf(CosinePdf::new(&rec.normal))),
             is_scattered: true,
@@ -65,16 +101,14 @@ impl Metal {
 impl Material for Metal {
     fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> ScatterRecord {
         let reflected = Metal::reflect(&ray_in.direction.as_V3(), &rec.normal);
-        let specular_ray = Ray {
-            origin: rec.point,
-            direction: V3U::new(reflected + V3::new_in_unit_sphere().scale(self.fuzz)),
-        };
+        let direction = reflected + V3::new_in_unit_sphere().scale(self.fuzz);
 
         ScatterRecord {
             attenuation: self.albedo,
-            specular_ray: Some(specular_ray),
+            specular_ray: Some(rec.spawn_ray(V3U::new(direction))),
             pdf: None,
-            is_scattered: true,
+            // fuzz can push the reflection below the surface, where it is absorbed
+            is_scattered: direction.dot(rec.normal) > 0.0,
         }
     }
 }
@@ -109,28 +143,29 @@ impl Dielectric {
 impl Material for Dielectric {
     fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> ScatterRecord {
         let reflected = Dielectric::reflect(&ray_in.direction.as_V3(), &rec.normal);
-        let (outward_normal, ni_over_nt, cosine) =
-            if ray_in.direction.dot(rec.normal) > 0.0 {
-                let cosine = self.ref_idx * ray_in.direction.dot(rec.normal);
-                (-rec.normal, self.ref_idx, cosine)
+        // some figures already turn the normal towards the ray, so inside/outside comes from front_face
+        let facing_normal = if ray_in.direction.dot(rec.normal) > 0.0 { -rec.normal } else { rec.normal };
+        let cosine = - ray_in.direction.dot(facing_normal);
+        let (ni_over_nt, cosine) =
+            if rec.front_face {
+                (1.0 / self.ref_idx, cosine)
             } else {
-                let cosine = - ray_in.direction.dot(rec.normal);
-                (rec.normal, 1.0 / self.ref_idx, cosine)
+                (self.ref_idx, self.ref_idx * cosine)
             };
 
-        if let Some(refracted) = Dielectric::refract(&ray_in.direction.as_V3(), outward_normal, ni_over_nt) {
+        if let Some(refracted) = Dielectric::refract(&ray_in.direction.as_V3(), facing_normal, ni_over_nt) {
             let reflect_prob = self.schlick(cosine);
 
             ScatterRecord {
                 attenuation: V3(1.0, 1.0, 1.0),
-                specular_ray: Some(Ray { origin: rec.point, direction: if rand::random::<f32>() < reflect_prob { V3U::new(reflected) } else { V3U::new(refracted) } }),
+                specular_ray: Some(rec.spawn_ray(if random() < reflect_prob { V3U::new(reflected) } else { V3U::new(refracted) })),
                 is_scattered: true,
                 pdf: None,
             }
         } else {
             ScatterRecord {
                 attenuation: V3(1.0, 1.0, 1.0),
-                specular_ray: Some(Ray { origin: rec.point, direction: V3U::new(reflected) }),
+                specular_ray: Some(rec.spawn_ray(V3U::new(reflected))),
                 is_scattered: true,
                 pdf: None,
             }
@@ -138,15 +173,34 @@ impl Material for Dielectric {
     }
 }
 
-struct DiffuseLight {
+// how an area light's radiance falls off away from its normal
+#[derive(Clone, Copy, PartialEq)]
+pub enum EmissionProfile {
+    // the same radiance in every direction, like a softbox
+    Lambertian,
+    // radiance scaled by cos^n of the angle to the normal, like a bare LED panel; higher n makes a tighter beam
+    Focused(f32),
+}
+
+impl EmissionProfile {
+    fn falloff(&self, cosine: f32) -> f32 {
+        match self {
+            EmissionProfile::Lambertian => 1.0,
+            EmissionProfile::Focused(exponent) => cosine.max(0.0).powf(*exponent),
+        }
+    }
+}
+
+pub struct DiffuseLight {
     emit: Textures,
+    profile: EmissionProfile,
 }
 
 impl Material for DiffuseLight {
     fn scatter(&self, _ray_in: &Ray, _hit_record: &HitRecord) -> ScatterRecord {
         ScatterRecord {
             attenuation: V3(0.0, 0.0, 0.0),
-            specular_ray: Some(Ray { origin: V3(0.0, 0.0, 0.0), direction: V3U::new(V3(1.0, 0.0, 0.0)) }),
+            specular_ray: Some(Ray { origin: V3(0.0, 0.0, 0.0), direction: V3U::from_normalized_unchecked(V3(1.0, 0.0, 0.0)), time: 0.0, wavelength: None }),
             is_scattered: false,
             pdf: None,
         }
@@ -157,11 +211,219 @@ impl Material for DiffuseLight {
     }
 }
 
+// lambertian base under a phong (cosine power) highlight; each scatter picks one of the two lobes with
+// probability specular_strength, so the lobes' weights don't need to be applied to the attenuation
+pub struct Plastic {
+    diffuse: Textures,
+    specular_strength: f32,
+    shininess: f32,
+}
+
+impl Plastic {
+    fn phong_direction(&self, reflected: &V3) -> V3 {
+        let (r1, r2) = (random(), random());
+        let cos_theta = r2.powf(1.0 / (self.shininess + 1.0));
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * r1;
+
+        Onb::new_from_w(reflected).local(&V3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta))
+    }
+}
+
+impl Material for Plastic {
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> ScatterRecord {
+        if random() < self.specular_strength {
+            let reflected = Metal::reflect(&ray_in.direction.as_V3(), &rec.normal);
+            let direction = self.phong_direction(&reflected);
+
+            ScatterRecord {
+                attenuation: V3(1.0, 1.0, 1.0),
+                specular_ray: Some(rec.spawn_ray(V3U::new(direction))),
+                pdf: None,
+                // lobe samples below the surface are absorbed
+                is_scattered: direction.dot(rec.normal) > 0.0,
+            }
+        } else {
+            ScatterRecord {
+                attenuation: rec.color.unwrap_or_else(|| self.diffuse.value(rec.u, rec.v, &rec.point)),
+                specular_ray: None,
+                pdf: Some(Pdfs::CosinePdf(CosinePdf::new(&rec.normal))),
+                is_scattered: true,
+            }
+        }
+    }
+
+    fn scattering_pdf(&self, _ray_in: &Ray, hit_record: &HitRecord, scattered: &Ray) -> f32 {
+        let cosine = hit_record.normal.dot(scattered.direction);
+        if cosine < 0.0 { 0.0 } else { cosine / std::f32::consts::PI }
+    }
+}
+
+// a rough conductor with a GGX microfacet lobe. Unlike Metal it reports a pdf instead of a specular ray, so the
+// lobe is mixed with light sampling like a diffuse surface. The Fresnel term is approximated by the albedo (F0)
+pub struct RoughMetal {
+    albedo: V3,
+    alpha: f32,
+}
+
+impl RoughMetal {
+    fn facing_normal(ray_in: &Ray, rec: &HitRecord) -> V3 {
+        if ray_in.direction.dot(rec.normal) > 0.0 { -rec.normal } else { rec.normal }
+    }
+}
+
+impl Material for RoughMetal {
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> ScatterRecord {
+        let normal = RoughMetal::facing_normal(ray_in, rec);
+        ScatterRecord {
+            attenuation: self.albedo,
+            specular_ray: None,
+            pdf: Some(Pdfs::GgxPdf(GgxPdf::new(&normal, -ray_in.direction.as_V3(), self.alpha))),
+            is_scattered: true,
+        }
+    }
+
+    // brdf * cos(theta_i) without the albedo: D G / (4 cos(theta_o))
+    fn scattering_pdf(&self, ray_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f32 {
+        let normal = RoughMetal::facing_normal(ray_in, rec);
+        let outgoing = -ray_in.direction.as_V3();
+        let incoming = scattered.direction.as_V3();
+        let (cos_o, cos_i) = (outgoing.dot(normal), incoming.dot(normal));
+        if cos_o <= 0.0 || cos_i <= 0.0 {
+            return 0.0;
+        }
+
+        let half = (outgoing + incoming).normalize();
+        let d = GgxPdf::distribution(half.dot(normal), self.alpha);
+        let g = GgxPdf::masking(cos_o, self.alpha) * GgxPdf::masking(cos_i, self.alpha);
+        d * g / (4.0 * cos_o)
+    }
+}
+
+// entries of Cloth's directional albedo table over cos(theta_o)
+const SHEEN_ALBEDO_STEPS: usize = 16;
+
+// fabric: a lambertian base under a sheen lobe (Estevez and Kulla's "Charlie" distribution with Neubelt and
+// Pettineo's visibility term) that brightens grazing and back-lit edges. Like Plastic each scatter picks one lobe;
+// the sheen is picked with probability of its directional albedo, which is also the share taken from the base
+pub struct Cloth {
+    base: Textures,
+    sheen_color: V3,
+    alpha: f32,
+    // directional albedo of the uncolored sheen lobe, tabulated over cos(theta_o)
+    albedo: Vec<f32>,
+}
+
+impl Cloth {
+    fn new(base: Textures, sheen_color: V3, alpha: f32) -> Cloth {
+        let mut cloth = Cloth {
+            base: base,
+            sheen_color: sheen_color,
+            alpha: alpha,
+            albedo: vec![],
+        };
+
+        // midpoint rule over cos(theta_i) and phi, which covers the hemisphere with equal solid angles
+        let (n_cos, n_phi) = (32, 64);
+        let normal = V3(0.0, 0.0, 1.0);
+        cloth.albedo = (0..SHEEN_ALBEDO_STEPS).map(|k| {
+            let cos_o = (k as f32 + 0.5) / SHEEN_ALBEDO_STEPS as f32;
+            let outgoing = V3((1.0 - cos_o * cos_o).sqrt(), 0.0, cos_o);
+            let mut sum = 0.0;
+            for a in 0..n_cos {
+                let cos_i = (a as f32 + 0.5) / n_cos as f32;
+                let sin_i = (1.0 - cos_i * cos_i).sqrt();
+                for b in 0..n_phi {
+                    let phi = 2.0 * std::f32::consts::PI * (b as f32 + 0.5) / n_phi as f32;
+                    let incoming = V3(sin_i * phi.cos(), sin_i * phi.sin(), cos_i);
+                    sum += cloth.sheen_brdf(&normal, &outgoing, &incoming) * cos_i;
+                }
+            }
+            sum * 2.0 * std::f32::consts::PI / (n_cos * n_phi) as f32
+        }).collect();
+        cloth
+    }
+
+    fn strength(&self) -> f32 {
+        self.sheen_color.x().max(self.sheen_color.y()).max(self.sheen_color.z())
+    }
+
+    fn sheen_albedo(&self, cos_o: f32) -> f32 {
+        let k = (cos_o * SHEEN_ALBEDO_STEPS as f32) as usize;
+        self.albedo[k.min(SHEEN_ALBEDO_STEPS - 1)]
+    }
+
+    fn sheen_brdf(&self, normal: &V3, outgoing: &V3, incoming: &V3) -> f32 {
+        let (cos_o, cos_i) = (outgoing.dot(*normal), incoming.dot(*normal));
+        let cos_h = (*outgoing + *incoming).normalize().dot(*normal);
+        let sin_h = (1.0 - cos_h * cos_h).max(0.0).sqrt();
+        let distribution = (2.0 + 1.0 / self.alpha) * sin_h.powf(1.0 / self.alpha) / (2.0 * std::f32::consts::PI);
+        distribution / (4.0 * (cos_i + cos_o - cos_i * cos_o))
+    }
+}
+
+impl Material for Cloth {
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> ScatterRecord {
+        let normal = RoughMetal::facing_normal(ray_in, rec);
+        let outgoing = -ray_in.direction.as_V3();
+        let sheen_albedo = self.sheen_albedo(outgoing.dot(normal).max(0.0));
+        let weight = self.strength() * sheen_albedo;
+
+        if weight > 0.0 && random() < weight {
+            // uniform over the hemisphere, pdf 1 / 2pi
+            let (r1, r2) = (random(), random());
+            let sin_theta = (1.0 - r1 * r1).sqrt();
+            let phi = 2.0 * std::f32::consts::PI * r2;
+            let incoming = Onb::new_from_w(&normal).local(&V3(sin_theta * phi.cos(), sin_theta * phi.sin(), r1));
+            let reflected = self.sheen_brdf(&normal, &outgoing, &incoming) * r1 * 2.0 * std::f32::consts::PI;
+
+            ScatterRecord {
+                attenuation: self.sheen_color.scale(reflected / weight),
+                specular_ray: Some(rec.spawn_ray(V3U::new(incoming))),
+                pdf: None,
+                is_scattered: true,
+            }
+        } else {
+            ScatterRecord {
+                attenuation: rec.color.unwrap_or_else(|| self.base.value(rec.u, rec.v, &rec.point)),
+                specular_ray: None,
+                pdf: Some(Pdfs::CosinePdf(CosinePdf::new(&rec.normal))),
+                is_scattered: true,
+            }
+        }
+    }
+
+    fn scattering_pdf(&self, _ray_in: &Ray, hit_record: &HitRecord, scattered: &Ray) -> f32 {
+        let cosine = hit_record.normal.dot(scattered.direction);
+        if cosine < 0.0 { 0.0 } else { cosine / std::f32::consts::PI }
+    }
+}
+
+// scatters uniformly in every direction; used inside participating media where there is no surface normal
+pub struct Isotropic {
+    albedo: Textures,
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, _ray_in: &Ray, rec: &HitRecord) -> ScatterRecord {
+        ScatterRecord {
+            attenuation: self.albedo.value(rec.u, rec.v, &rec.point),
+            specular_ray: Some(Ray { origin: rec.point, direction: V3U::new(V3::new_in_unit_sphere()), time: rec.time, wavelength: rec.wavelength }),
+            is_scattered: true,
+            pdf: None,
+        }
+    }
+}
+
 pub enum Materials {
     Lambertian(Lambertian),
     Metal(Metal),
     Dielectric(Dielectric),
     DiffuseLight(DiffuseLight),
+    Isotropic(Isotropic),
+    Plastic(Plastic),
+    RoughMetal(RoughMetal),
+    Cloth(Cloth),
 }
 
 impl Materials {
@@ -185,17 +447,88 @@ impl Materials {
     }
 
     pub fn diffuse_light(emit: Textures) -> Materials {
+        Materials::diffuse_light_with(emit, EmissionProfile::Lambertian)
+    }
+
+    // emit is the radiance along the normal; a focused profile keeps it there and dims every other direction
+    pub fn diffuse_light_with(emit: Textures, profile: EmissionProfile) -> Materials {
         Materials::DiffuseLight(DiffuseLight {
-            emit: emit
+            emit: emit,
+            profile: profile,
         })
     }
 
+    // e.g. 3200K for a warm tungsten bulb, 6500K for daylight; intensity is the luminance of the emission
+    pub fn diffuse_light_kelvin(temperature_k: f32, intensity: f32) -> Materials {
+        Materials::diffuse_light(Textures::solid(blackbody_to_rgb(temperature_k).scale(intensity)))
+    }
+
+    pub fn isotropic(albedo: Textures) -> Materials {
+        Materials::Isotropic(Isotropic {
+            albedo: albedo
+        })
+    }
+
+    pub fn plastic(diffuse: Textures, specular_strength: f32, shininess: f32) -> Materials {
+        Materials::Plastic(Plastic {
+            diffuse: diffuse,
+            specular_strength: specular_strength.max(0.0).min(1.0),
+            shininess: shininess.max(0.0),
+        })
+    }
+
+    // roughness in (0,1]; alpha = roughness^2 as usual, kept away from 0 where the lobe becomes a delta
+    pub fn rough_metal(albedo: V3, roughness: f32) -> Materials {
+        let roughness = roughness.max(0.02).min(1.0);
+        Materials::RoughMetal(RoughMetal {
+            albedo: albedo,
+            alpha: roughness * roughness,
+        })
+    }
+
+    // sheen_color is the tint of the rim, usually a lighter version of the base; sheen_roughness in (0,1] spreads it
+    // from a thin highlight at the silhouette to a soft haze over the whole surface
+    pub fn cloth(base: Textures, sheen_color: V3, sheen_roughness: f32) -> Materials {
+        let roughness = sheen_roughness.max(0.07).min(1.0);
+        Materials::Cloth(Cloth::new(base, sheen_color.map(&|c| c.max(0.0).min(1.0)), roughness * roughness))
+    }
+
+    // view dependent reflection that a position-keyed cache can't reuse
+    pub fn is_glossy(&self) -> bool {
+        match self {
+            Materials::RoughMetal(_) => true,
+            _ => false,
+        }
+    }
+
+    // smooth metals with their roughness raised to min_roughness, as GGX so that their highlights can be light
+    // sampled like any other glossy surface (Metal's fuzz and RoughMetal's r, with alpha = r^2, both count as
+    // roughness); None when the material is already rough enough or isn't a metal
+    pub fn with_min_roughness(&self, min_roughness: f32) -> Option<Materials> {
+        match self {
+            Materials::Metal(m) if m.fuzz < min_roughness => Some(Materials::rough_metal(m.albedo, min_roughness)),
+            Materials::RoughMetal(m) if m.alpha < min_roughness * min_roughness => Some(Materials::rough_metal(m.albedo, min_roughness)),
+            _ => None,
+        }
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        match self {
+            Materials::DiffuseLight(_) => true,
+            _ => false,
+        }
+    }
+
     pub fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> ScatterRecord {
         match self {
             Materials::Lambertian(m) => m.scatter(ray_in, hit_record),
             Materials::Metal(m) => m.scatter(ray_in, hit_record),
             Materials::Dielectric(m) => m.scatter(ray_in, hit_record),
             Materials::DiffuseLight(m) => m.scatter(ray_in, hit_record),
+            Materials::Isotropic(m) => m.scatter(ray_in, hit_record),
+            Materials::Plastic(m) => m.scatter(ray_in, hit_record),
+            Materials::RoughMetal(m) => m.scatter(ray_in, hit_record),
+            Materials::Cloth(m) => m.scatter(ray_in, hit_record),
         }
     }
 
@@ -205,6 +538,10 @@ impl Materials {
             Materials::Metal(m) => m.scattering_pdf(ray_in, hit_record, scattered),
             Materials::Dielectric(m) => m.scattering_pdf(ray_in, hit_record, scattered),
             Materials::DiffuseLight(m) => m.scattering_pdf(ray_in, hit_record, scattered),
+            Materials::Isotropic(m) => m.scattering_pdf(ray_in, hit_record, scattered),
+            Materials::Plastic(m) => m.scattering_pdf(ray_in, hit_record, scattered),
+            Materials::RoughMetal(m) => m.scattering_pdf(ray_in, hit_record, scattered),
+            Materials::Cloth(m) => m.scattering_pdf(ray_in, hit_record, scattered),
         }
     }
 
@@ -214,7 +551,111 @@ impl Materials {
             Materials::Metal(m) => m.emitted(u,v,point),
             Materials::Dielectric(m) => m.emitted(u,v,point),
             Materials::DiffuseLight(m) => m.emitted(u,v,point),
+            Materials::Isotropic(m) => m.emitted(u,v,point),
+            Materials::Plastic(m) => m.emitted(u,v,point),
+            Materials::RoughMetal(m) => m.emitted(u,v,point),
+            Materials::Cloth(m) => m.emitted(u,v,point),
+        }
+    }
+
+    // the radiance rec's surface sends back along ray, which includes the emission profile of area lights
+    pub fn emitted_along(&self, ray: &Ray, rec: &HitRecord) -> V3 {
+        self.emitted(rec.u, rec.v, &rec.point).scale(self.emission_falloff(ray, rec))
+    }
+
+    // the fraction of its on-axis radiance an area light sends back along ray; 1 for everything else
+    pub fn emission_falloff(&self, ray: &Ray, rec: &HitRecord) -> f32 {
+        match self {
+            Materials::DiffuseLight(m) => {
+                // some figures turn the normal towards the ray, so the emitting side comes from front_face; seen
+                // from behind the cosine is negative, where a focused light is dark
+                let cosine = ray.direction.dot(rec.normal).abs();
+                m.profile.falloff(if rec.front_face { cosine } else { -cosine })
+            },
+            _ => 1.0,
+        }
+    }
+
+    pub fn is_focused_light(&self) -> bool {
+        match self {
+            Materials::DiffuseLight(m) => m.profile != EmissionProfile::Lambertian,
+            _ => false,
         }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    // on a planet-sized sphere, t_min alone is far below the f32 spacing of the hit points, so bounced rays start
+    // inside the surface and shadow themselves
+    #[test]
+    fn spawned_rays_clear_a_large_sphere() {
+        let ground = Figures::sphere(V3(0.0, -100_000.0, 0.0), 100_000.0);
+        let mut rng = seeded_rng(4);
+        let (mut acne, mut spawned_acne) = (0, 0);
+        for _ in 0..500 {
+            let origin = V3(rng.gen::<f32>() * 2000.0 - 1000.0, 10.0, rng.gen::<f32>() * 2000.0 - 1000.0);
+            let rec = ground.hit(&Ray { origin: origin, direction: V3U::new(V3(0.3, -1.0, 0.2)), time: 0.0, wavelength: None }, 0.001, std::f32::MAX).unwrap();
+            let direction = V3U::new(Onb::new_from_w(&rec.normal).local(&Onb::cosine_direction_from(rng.gen())));
+
+            if ground.hit(&Ray { origin: rec.point, direction: direction, time: 0.0, wavelength: None }, 0.001, std::f32::MAX).is_some() {
+                acne += 1;
+            }
+            if ground.hit(&rec.spawn_ray(direction), 0.001, std::f32::MAX).is_some() {
+                spawned_acne += 1;
+            }
+        }
+        assert!(acne > 0);
+        assert!(spawned_acne == 0);
+    }
+
+    // at grazing incidence a fuzz of 1 sends about half the reflections below the surface; those must be absorbed
+    // rather than traced on as if they had reflected
+    #[test]
+    fn grazing_fuzzy_metal_rejects_rays_below_the_surface() {
+        let metal = Materials::metal(V3(1.0, 1.0, 1.0), 1.0);
+        let floor = Figures::xz_rect(-10.0, 10.0, -10.0, 10.0, 0.0);
+        let ray_in = Ray { origin: V3(-5.0, 0.05, 0.0), direction: V3U::new(V3(1.0, -0.01, 0.0)), time: 0.0, wavelength: None };
+        let rec = floor.hit(&ray_in, 0.001, std::f32::MAX).unwrap();
+
+        reseed_stream(3);
+        let (mut scattered, mut rejected) = (0, 0);
+        for _ in 0..1000 {
+            let scatter = metal.scatter(&ray_in, &rec);
+            let direction = scatter.specular_ray.unwrap().direction;
+            if scatter.is_scattered {
+                assert!(direction.dot(rec.normal) > 0.0);
+                scattered += 1;
+            } else {
+                assert!(direction.dot(rec.normal) <= 0.0);
+                rejected += 1;
+            }
+        }
+        assert!(scattered > 300 && rejected > 300);
+    }
+
+    #[test]
+    fn focused_light_is_dark_from_behind() {
+        let panel = Figures::xz_rect(-1.0, 1.0, -1.0, 1.0, 0.0);
+        let from_above = Ray { origin: V3(0.0, 1.0, 0.0), direction: V3U::new(V3(0.6, -1.0, 0.0)), time: 0.0, wavelength: None };
+        let from_below = Ray { origin: V3(0.0, -1.0, 0.0), direction: V3U::new(V3(0.6, 1.0, 0.0)), time: 0.0, wavelength: None };
+        let (above, below) = (panel.hit(&from_above, 0.001, std::f32::MAX).unwrap(), panel.hit(&from_below, 0.001, std::f32::MAX).unwrap());
+
+        let focused = Materials::diffuse_light_with(Textures::solid(V3(1.0, 1.0, 1.0)), EmissionProfile::Focused(2.0));
+        let cosine = 1.0 / (1.0f32 + 0.36).sqrt();
+        assert!((focused.emission_falloff(&from_above, &above) - cosine * cosine).abs() < 1e-5);
+        assert!(focused.emission_falloff(&from_below, &below) == 0.0);
+
+        // a flipped panel emits downwards instead
+        let flipped = Figures::flip_normals(panel);
+        assert!(focused.emission_falloff(&from_above, &flipped.hit(&from_above, 0.001, std::f32::MAX).unwrap()) == 0.0);
+        assert!(focused.emission_falloff(&from_below, &flipped.hit(&from_below, 0.001, std::f32::MAX).unwrap()) > 0.0);
+
+        let lambertian = Materials::diffuse_light(Textures::solid(V3(1.0, 1.0, 1.0)));
+        assert!(lambertian.emission_falloff(&from_above, &above) == 1.0 && lambertian.emission_falloff(&from_below, &below) == 1.0);
+    }
+}
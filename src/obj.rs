@@ -0,0 +1,126 @@
+use std::fs;
+use std::io;
+use crate::vector::*;
+use crate::figures::*;
+
+struct ObjVertex {
+    position: usize,
+    texcoord: Option<usize>,
+    normal: Option<usize>,
+}
+
+impl ObjVertex {
+    // resolves a face corner such as `3`, `3/1`, `3//2` or `3/1/2`; indices are 1-based or negative (relative)
+    fn parse(token: &str, positions: usize, texcoords: usize, normals: usize) -> Option<ObjVertex> {
+        let resolve = |s: &str, count: usize| -> Option<usize> {
+            let i = s.parse::<i64>().ok()?;
+            let index = if i < 0 { count as i64 + i } else { i - 1 };
+            if 0 <= index && index < count as i64 { Some(index as usize) } else { None }
+        };
+
+        let mut parts = token.split('/');
+        let position = resolve(parts.next()?, positions)?;
+        let texcoord = match parts.next() {
+            Some(s) if !s.is_empty() => Some(resolve(s, texcoords)?),
+            _ => None,
+        };
+        let normal = match parts.next() {
+            Some(s) if !s.is_empty() => Some(resolve(s, normals)?),
+            _ => None,
+        };
+
+        Some(ObjVertex {
+            position: position,
+            texcoord: texcoord,
+            normal: normal,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct ObjOptions {
+    // ignore the file's vertex normals and shade with each face's geometric normal, to inspect the topology
+    pub flat_shading: bool,
+}
+
+impl Figures {
+    pub fn load_obj(file_name: &str) -> io::Result<Figures> {
+        Figures::load_obj_with(file_name, ObjOptions::default())
+    }
+
+    pub fn load_obj_with(file_name: &str, options: ObjOptions) -> io::Result<Figures> {
+        let source = fs::read_to_string(file_name)?;
+        let invalid = |line: usize, message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: {}", file_name, line + 1, message));
+
+        let mut positions = vec![];
+        let mut colors = vec![];
+        let mut normals = vec![];
+        let mut texcoords = vec![];
+        let mut triangles = vec![];
+
+        for (n, line) in source.lines().enumerate() {
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("v") => {
+                    // a common extension appends an rgb color to the position
+                    let values = tokens.take(6).map(|t| t.parse::<f32>()).collect::<Result<Vec<f32>, _>>().map_err(|_| invalid(n, "malformed vector"))?;
+                    if values.len() < 3 {
+                        return Err(invalid(n, "expected three components"));
+                    }
+
+                    positions.push(V3(values[0], values[1], values[2]));
+                    colors.push(if values.len() == 6 { Some(V3(values[3], values[4], values[5])) } else { None });
+                },
+                Some("vn") => {
+                    let values = tokens.take(3).map(|t| t.parse::<f32>()).collect::<Result<Vec<f32>, _>>().map_err(|_| invalid(n, "malformed vector"))?;
+                    if values.len() != 3 {
+                        return Err(invalid(n, "expected three components"));
+                    }
+
+                    normals.push(V3(values[0], values[1], values[2]));
+                },
+                Some("vt") => {
+                    let values = tokens.take(2).map(|t| t.parse::<f32>()).collect::<Result<Vec<f32>, _>>().map_err(|_| invalid(n, "malformed texture coordinate"))?;
+                    if values.is_empty() {
+                        return Err(invalid(n, "expected texture coordinates"));
+                    }
+
+                    texcoords.push((values[0], *values.get(1).unwrap_or(&0.0)));
+                },
+                Some("f") => {
+                    let corners = tokens.map(|t| ObjVertex::parse(t, positions.len(), texcoords.len(), normals.len()))
+                        .collect::<Option<Vec<ObjVertex>>>()
+                        .ok_or_else(|| invalid(n, "malformed face"))?;
+                    if corners.len() < 3 {
+                        return Err(invalid(n, "face needs at least three vertices"));
+                    }
+
+                    for i in 1..corners.len() - 1 {
+                        let (a, b, c) = (&corners[0], &corners[i], &corners[i + 1]);
+                        let (p0, p1, p2) = (positions[a.position], positions[b.position], positions[c.position]);
+
+                        let mut triangle = Triangle::new(p0, p1, p2);
+                        if let (false, Some(n0), Some(n1), Some(n2)) = (options.flat_shading, a.normal, b.normal, c.normal) {
+                            triangle = triangle.with_normals(normals[n0], normals[n1], normals[n2]);
+                        }
+                        if let (Some(t0), Some(t1), Some(t2)) = (a.texcoord, b.texcoord, c.texcoord) {
+                            triangle = triangle.with_texcoords(texcoords[t0], texcoords[t1], texcoords[t2]);
+                        }
+                        if let (Some(c0), Some(c1), Some(c2)) = (colors[a.position], colors[b.position], colors[c.position]) {
+                            triangle = triangle.with_colors(c0, c1, c2);
+                        }
+
+                        triangles.push(Figures::Triangle(triangle));
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        if triangles.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: no faces", file_name)));
+        }
+
+        Ok(Figures::bvh_node(triangles, 0.0, 1.0))
+    }
+}
@@ -1,9 +1,22 @@
 use crate::vector::*;
 use crate::figures::*;
+use crate::guiding::*;
+use crate::background::*;
+use crate::sampler::*;
 
 pub trait Pdf {
     fn value(&self, direction: &V3U) -> f32;
     fn generate(&self) -> V3;
+
+    fn generate_from(&self, _r: (f32, f32)) -> V3 {
+        self.generate()
+    }
+
+    // also tells whether the direction was aimed at a light (a HitPdf or the environment), which makes the ray
+    // it starts a shadow ray rather than a reflection
+    fn generate_aimed(&self, r: (f32, f32)) -> (V3, bool) {
+        (self.generate_from(r), false)
+    }
 }
 
 #[derive(Clone)]
@@ -32,6 +45,10 @@ impl Pdf for OnbPdf {
     fn generate(&self) -> V3 {
         self.uvw.local(&Onb::random_cosine_direction())
     }
+
+    fn generate_from(&self, r: (f32, f32)) -> V3 {
+        self.uvw.local(&Onb::cosine_direction_from(r))
+    }
 }
 
 #[derive(Clone)]
@@ -51,39 +68,75 @@ impl HitPdf {
 
 impl Pdf for HitPdf {
     fn value(&self, direction: &V3U) -> f32 {
-        self.figure.pdf_value(self.origin, *direction)
+        let value = self.figure.pdf_value(self.origin, *direction);
+        if value.is_finite() { value } else { 0.0 }
     }
 
     fn generate(&self) -> V3 {
         self.figure.random(self.origin)
     }
+
+    fn generate_from(&self, r: (f32, f32)) -> V3 {
+        self.figure.random_from(self.origin, r)
+    }
+
+    fn generate_aimed(&self, r: (f32, f32)) -> (V3, bool) {
+        (self.generate_from(r), true)
+    }
 }
 
 #[derive(Clone)]
 pub struct MixPdf {
     pdf: (Box<Pdfs>, Box<Pdfs>),
+    // probability of drawing from the first pdf
+    weight: f32,
 }
 
 impl MixPdf {
     pub fn new(p0: Pdfs, p1: Pdfs) -> MixPdf {
+        MixPdf::weighted(p0, p1, 0.5)
+    }
+
+    pub fn weighted(p0: Pdfs, p1: Pdfs, weight: f32) -> MixPdf {
         MixPdf {
-            pdf: (Box::new(p0), Box::new(p1))
+            pdf: (Box::new(p0), Box::new(p1)),
+            weight: weight.max(0.0).min(1.0),
         }
     }
 }
 
+// e.g. a light sample taken from a point in the light's own plane
+fn is_valid_direction(v: &V3) -> bool {
+    v.x().is_finite() && v.y().is_finite() && v.z().is_finite() && v.square_norm() > 0.0
+}
+
 impl Pdf for MixPdf {
     fn value(&self, direction: &V3U) -> f32 {
-        0.5 * self.pdf.0.value(direction) + 0.5 * self.pdf.1.value(direction)
+        self.weight * self.pdf.0.value(direction) + (1.0 - self.weight) * self.pdf.1.value(direction)
     }
 
+    // a degenerate direction from the first pdf (the light, in shade's mixtures) falls back to the second
     fn generate(&self) -> V3 {
-        if rand::random::<f32>() < 0.5 {
-            self.pdf.0.generate()
+        if random() < self.weight {
+            let v = self.pdf.0.generate();
+            if is_valid_direction(&v) { v } else { self.pdf.1.generate() }
         } else {
             self.pdf.1.generate()
         }
     }
+
+    fn generate_from(&self, r: (f32, f32)) -> V3 {
+        self.generate_aimed(r).0
+    }
+
+    fn generate_aimed(&self, r: (f32, f32)) -> (V3, bool) {
+        if random() < self.weight {
+            let (v, aimed) = self.pdf.0.generate_aimed(r);
+            if is_valid_direction(&v) { (v, aimed) } else { self.pdf.1.generate_aimed(r) }
+        } else {
+            self.pdf.1.generate_aimed(r)
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -112,6 +165,74 @@ impl Pdf for CosinePdf {
     fn generate(&self) -> V3 {
         self.uvw.local(&Onb::random_cosine_direction())
     }
+
+    fn generate_from(&self, r: (f32, f32)) -> V3 {
+        self.uvw.local(&Onb::cosine_direction_from(r))
+    }
+}
+
+// samples the GGX (Trowbridge-Reitz) half vector proportionally to D(h) cos(theta_h) and reflects the outgoing
+// direction about it, so the density of the reflected direction is D(h) cos(theta_h) / (4 |wo . h|)
+#[derive(Clone)]
+pub struct GgxPdf {
+    uvw: Onb,
+    outgoing: V3,
+    alpha: f32,
+}
+
+impl GgxPdf {
+    pub fn new(normal: &V3, outgoing: V3, alpha: f32) -> GgxPdf {
+        GgxPdf {
+            uvw: Onb::new_from_w(normal),
+            outgoing: outgoing,
+            alpha: alpha,
+        }
+    }
+
+    // normal distribution function
+    pub fn distribution(cos_h: f32, alpha: f32) -> f32 {
+        let a2 = alpha * alpha;
+        let d = cos_h * cos_h * (a2 - 1.0) + 1.0;
+        a2 / (std::f32::consts::PI * d * d)
+    }
+
+    // Smith masking for one direction
+    pub fn masking(cos: f32, alpha: f32) -> f32 {
+        let a2 = alpha * alpha;
+        2.0 * cos / (cos + (a2 + (1.0 - a2) * cos * cos).sqrt())
+    }
+}
+
+impl Pdf for GgxPdf {
+    fn value(&self, direction: &V3U) -> f32 {
+        let normal = self.uvw.w();
+        let half = self.outgoing + direction.as_V3();
+        if direction.dot(normal) <= 0.0 || half.square_norm() == 0.0 {
+            return 0.0;
+        }
+
+        let half = half.normalize();
+        let (cos_h, cos_oh) = (half.dot(normal), self.outgoing.dot(half));
+        if cos_h <= 0.0 || cos_oh <= 0.0 {
+            return 0.0;
+        }
+
+        GgxPdf::distribution(cos_h, self.alpha) * cos_h / (4.0 * cos_oh)
+    }
+
+    fn generate(&self) -> V3 {
+        self.generate_from((random(), random()))
+    }
+
+    fn generate_from(&self, r: (f32, f32)) -> V3 {
+        let a2 = self.alpha * self.alpha;
+        let cos_theta = ((1.0 - r.1) / (1.0 + (a2 - 1.0) * r.1)).max(0.0).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * r.0;
+        let half = self.uvw.local(&V3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+
+        half.scale(2.0 * self.outgoing.dot(half)) - self.outgoing
+    }
 }
 
 #[derive(Clone)]
@@ -119,6 +240,9 @@ pub enum Pdfs {
     MixPdf(MixPdf),
     CosinePdf(CosinePdf),
     HitPdf(HitPdf),
+    GuidePdf(GuidePdf),
+    EnvironmentPdf(EnvironmentPdf),
+    GgxPdf(GgxPdf),
 }
 
 impl Pdf for Pdfs {
@@ -127,6 +251,9 @@ impl Pdf for Pdfs {
             Pdfs::MixPdf(p) => p.value(direction),
             Pdfs::CosinePdf(p) => p.value(direction),
             Pdfs::HitPdf(p) => p.value(direction),
+            Pdfs::GuidePdf(p) => p.value(direction),
+            Pdfs::EnvironmentPdf(p) => p.value(direction),
+            Pdfs::GgxPdf(p) => p.value(direction),
         }
     }
 
@@ -135,7 +262,75 @@ impl Pdf for Pdfs {
             Pdfs::MixPdf(p) => p.generate(),
             Pdfs::CosinePdf(p) => p.generate(),
             Pdfs::HitPdf(p) => p.generate(),
+            Pdfs::GuidePdf(p) => p.generate(),
+            Pdfs::EnvironmentPdf(p) => p.generate(),
+            Pdfs::GgxPdf(p) => p.generate(),
+        }
+    }
+
+    fn generate_from(&self, r: (f32, f32)) -> V3 {
+        match self {
+            Pdfs::MixPdf(p) => p.generate_from(r),
+            Pdfs::CosinePdf(p) => p.generate_from(r),
+            Pdfs::HitPdf(p) => p.generate_from(r),
+            Pdfs::GuidePdf(p) => p.generate_from(r),
+            Pdfs::EnvironmentPdf(p) => p.generate_from(r),
+            Pdfs::GgxPdf(p) => p.generate_from(r),
+        }
+    }
+
+    fn generate_aimed(&self, r: (f32, f32)) -> (V3, bool) {
+        match self {
+            Pdfs::MixPdf(p) => p.generate_aimed(r),
+            Pdfs::CosinePdf(p) => p.generate_aimed(r),
+            Pdfs::HitPdf(p) => p.generate_aimed(r),
+            Pdfs::GuidePdf(p) => p.generate_aimed(r),
+            Pdfs::EnvironmentPdf(p) => p.generate_aimed(r),
+            Pdfs::GgxPdf(p) => p.generate_aimed(r),
         }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    fn light() -> Figures {
+        Figures::xz_rect(-1.0, 1.0, -1.0, 1.0, 5.0)
+    }
+
+    #[test]
+    fn hit_pdf_is_zero_off_the_light() {
+        let pdf = HitPdf::new(light(), V3(0.0, 0.0, 0.0));
+        assert!(pdf.value(&V3U::new(V3(0.0, -1.0, 0.0))) == 0.0);
+        assert!(pdf.value(&V3U::new(V3(0.0, 1.0, 0.0))) > 0.0);
+    }
+
+    // from a point in the light's plane every direction grazes the light, where its pdf would be infinite
+    #[test]
+    fn coplanar_light_pdf_stays_finite() {
+        let origin = V3(3.0, 5.0, 0.0);
+        let hit = HitPdf::new(light(), origin);
+        assert!(hit.value(&V3U::new(V3(-1.0, 0.0, 0.0))) == 0.0);
+
+        let mix = MixPdf::new(Pdfs::HitPdf(hit), Pdfs::CosinePdf(CosinePdf::new(&V3(0.0, -1.0, 0.0))));
+        let mut rng = seeded_rng(1);
+        for _ in 0..100 {
+            let direction = mix.generate_from(rng.gen());
+            assert!(is_valid_direction(&direction));
+            assert!(mix.value(&V3U::new(direction)).is_finite());
+        }
+    }
+
+    // a direction neither side of the mixture can produce has zero density, which shade treats as no sample
+    #[test]
+    fn mix_pdf_is_zero_outside_both_pdfs() {
+        let mix = MixPdf::new(
+            Pdfs::HitPdf(HitPdf::new(light(), V3(0.0, 0.0, 0.0))),
+            Pdfs::CosinePdf(CosinePdf::new(&V3(0.0, 1.0, 0.0))),
+        );
+        assert!(mix.value(&V3U::new(V3(0.0, -1.0, 0.0))) == 0.0);
+    }
+}
@@ -0,0 +1,291 @@
+use std::cell::RefCell;
+use rand::{Rng, SeedableRng};
+use rand::prng::XorShiftRng;
+
+const PRIMES: [u32; 16] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53];
+
+thread_local! {
+    // the numbers materials, media and pdf mixtures draw besides the sampler's; RenderSettings::sampler_for reseeds
+    // it for every pixel sample, so a render is repeatable for a given seed
+    static STREAM: RefCell<XorShiftRng> = RefCell::new(seeded_rng(0));
+}
+
+// a uniform number in [0, 1) from the shared stream
+pub fn random() -> f32 {
+    STREAM.with(|stream| stream.borrow_mut().gen::<f32>())
+}
+
+pub fn with_stream<T, F: FnOnce(&mut XorShiftRng) -> T>(f: F) -> T {
+    STREAM.with(|stream| f(&mut stream.borrow_mut()))
+}
+
+pub fn reseed_stream(seed: u64) {
+    STREAM.with(|stream| *stream.borrow_mut() = seeded_rng(seed));
+}
+
+// xorshift has no all-zero state, so the seed is spread over both halves together with its complement
+pub fn seeded_rng(seed: u64) -> XorShiftRng {
+    let seed = seed ^ 0x9e37_79b9_7f4a_7c15;
+    let mut seed_bytes = [0u8; 16];
+    for i in 0..8 {
+        seed_bytes[i] = (seed >> (8 * i)) as u8;
+        seed_bytes[8 + i] = (!seed >> (8 * i)) as u8;
+    }
+    XorShiftRng::from_seed(seed_bytes)
+}
+
+// one seed per (pass index, pixel); the pixel already has the render seed mixed in
+pub fn sample_seed(index: u32, pixel: u32) -> u64 {
+    let mut h = (u64::from(index) << 32 | u64::from(pixel)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h ^= h >> 31;
+    h = h.wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 29)
+}
+
+pub trait Sampler {
+    fn next_2d(&mut self) -> (f32, f32);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SamplerKind {
+    Random,
+    Halton,
+    // stratifies the first 2d sample (the pixel jitter) over `samples` passes, with strata that are square on
+    // screen for pixels of the given width/height; the remaining dimensions are random
+    Stratified { samples: u32, pixel_aspect: f32 },
+}
+
+pub struct RandomSampler {
+    rng: XorShiftRng,
+}
+
+impl RandomSampler {
+    pub fn new(seed: u64) -> RandomSampler {
+        RandomSampler {
+            rng: seeded_rng(seed),
+        }
+    }
+}
+
+impl Sampler for RandomSampler {
+    fn next_2d(&mut self) -> (f32, f32) {
+        (self.rng.gen::<f32>(), self.rng.gen::<f32>())
+    }
+}
+
+pub struct HaltonSampler {
+    index: u32,
+    pixel: u32,
+    dimension: usize,
+    // for the dimensions past the last prime
+    fallback: RandomSampler,
+}
+
+impl HaltonSampler {
+    pub fn new(index: u32, pixel: u32) -> HaltonSampler {
+        HaltonSampler {
+            index: index,
+            pixel: pixel,
+            dimension: 0,
+            fallback: RandomSampler::new(sample_seed(index, pixel)),
+        }
+    }
+
+    fn radical_inverse(base: u32, mut index: u32) -> f32 {
+        let inv_base = 1.0 / base as f64;
+        let mut inv = inv_base;
+        let mut result = 0.0;
+        while index > 0 {
+            result += (index % base) as f64 * inv;
+            index /= base;
+            inv *= inv_base;
+        }
+
+        result as f32
+    }
+
+    // per-pixel Cranley-Patterson rotation so that neighbouring pixels don't share the same pattern
+    fn offset(&self, dimension: usize) -> f32 {
+        let mut h = self.pixel.wrapping_mul(0x9e37_79b9) ^ (dimension as u32).wrapping_mul(0x85eb_ca6b);
+        h ^= h >> 16;
+        h = h.wrapping_mul(0x7feb_352d);
+        h ^= h >> 15;
+        h = h.wrapping_mul(0x846c_a68b);
+        h ^= h >> 16;
+        (h >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    fn component(&self, dimension: usize) -> f32 {
+        let x = HaltonSampler::radical_inverse(PRIMES[dimension], self.index) + self.offset(dimension);
+        if x >= 1.0 { x - 1.0 } else { x }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn next_2d(&mut self) -> (f32, f32) {
+        if self.dimension + 1 >= PRIMES.len() {
+            return self.fallback.next_2d();
+        }
+
+        let r = (self.component(self.dimension), self.component(self.dimension + 1));
+        self.dimension += 2;
+        r
+    }
+}
+
+pub struct StratifiedSampler {
+    index: u32,
+    pixel: u32,
+    columns: u32,
+    rows: u32,
+    dimension: usize,
+    // jitter within the stratum and the remaining dimensions
+    fallback: RandomSampler,
+}
+
+impl StratifiedSampler {
+    pub fn new(index: u32, pixel: u32, samples: u32, pixel_aspect: f32) -> StratifiedSampler {
+        // columns / rows ~ pixel_aspect makes each stratum as wide on screen as it is tall
+        let columns = ((samples as f32 * pixel_aspect).sqrt().round() as u32).max(1).min(samples.max(1));
+        let rows = (samples / columns).max(1);
+
+        StratifiedSampler {
+            index: index,
+            pixel: pixel,
+            columns: columns,
+            rows: rows,
+            dimension: 0,
+            fallback: RandomSampler::new(sample_seed(index, pixel)),
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn next_2d(&mut self) -> (f32, f32) {
+        let first = self.dimension == 0;
+        self.dimension += 1;
+        let strata = self.columns * self.rows;
+        if !first || self.index >= strata {
+            return self.fallback.next_2d();
+        }
+
+        // rotate the stratum order per pixel so that one pass doesn't put every pixel's sample in the same corner
+        let stratum = (self.index + self.pixel.wrapping_mul(0x9e37_79b9) % strata) % strata;
+        let (column, row) = (stratum % self.columns, stratum / self.columns);
+        let (du, dv) = self.fallback.next_2d();
+        (
+            (column as f32 + du) / self.columns as f32,
+            (row as f32 + dv) / self.rows as f32,
+        )
+    }
+}
+
+// n jittered points on the unit square, one per cell of a grid with at least n cells; when the grid has spare
+// cells the used ones are picked at random, so every point is still uniformly distributed
+pub fn stratified_2d<R: Rng>(n: usize, rng: &mut R) -> Vec<(f32, f32)> {
+    let columns = ((n as f32).sqrt().ceil() as usize).max(1);
+    let rows = n.div_ceil(columns);
+    let mut cells = (0..columns * rows).collect::<Vec<usize>>();
+    rng.shuffle(&mut cells);
+
+    cells[..n].iter().map(|&cell| {
+        let (column, row) = (cell % columns, cell / columns);
+        (
+            (column as f32 + rng.gen::<f32>()) / columns as f32,
+            (row as f32 + rng.gen::<f32>()) / rows as f32,
+        )
+    }).collect()
+}
+
+pub enum Samplers {
+    Random(RandomSampler),
+    Halton(HaltonSampler),
+    Stratified(StratifiedSampler),
+}
+
+impl Samplers {
+    pub fn new(kind: SamplerKind, index: u32, pixel: u32) -> Samplers {
+        match kind {
+            SamplerKind::Random => Samplers::Random(RandomSampler::new(sample_seed(index, pixel))),
+            SamplerKind::Halton => Samplers::Halton(HaltonSampler::new(index, pixel)),
+            SamplerKind::Stratified { samples, pixel_aspect } => {
+                Samplers::Stratified(StratifiedSampler::new(index, pixel, samples, pixel_aspect))
+            },
+        }
+    }
+
+    // a generator seeded from this sampler's next sample, for places that need many numbers at once; it follows
+    // the render seed like the sampler itself
+    pub fn rng(&mut self) -> XorShiftRng {
+        let (a, b) = self.next_2d();
+        seeded_rng(u64::from(a.to_bits()) << 32 | u64::from(b.to_bits()))
+    }
+}
+
+impl Sampler for Samplers {
+    fn next_2d(&mut self) -> (f32, f32) {
+        match self {
+            Samplers::Random(s) => s.next_2d(),
+            Samplers::Halton(s) => s.next_2d(),
+            Samplers::Stratified(s) => s.next_2d(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(sampler: &mut Samplers, n: usize) -> Vec<(f32, f32)> {
+        (0..n).map(|_| sampler.next_2d()).collect()
+    }
+
+    #[test]
+    fn samplers_repeat_for_the_same_index_and_pixel() {
+        let kinds = [SamplerKind::Random, SamplerKind::Halton, SamplerKind::Stratified { samples: 16, pixel_aspect: 1.0 }];
+        for &kind in kinds.iter() {
+            // past the Halton primes, so the random fallback is covered too
+            let a = draw(&mut Samplers::new(kind, 3, 17), 12);
+            let b = draw(&mut Samplers::new(kind, 3, 17), 12);
+            let c = draw(&mut Samplers::new(kind, 3, 18), 12);
+            assert!(a == b);
+            assert!(a != c);
+        }
+    }
+
+    #[test]
+    fn stream_follows_its_seed() {
+        reseed_stream(42);
+        let a = (0..8).map(|_| random()).collect::<Vec<f32>>();
+        reseed_stream(42);
+        let b = (0..8).map(|_| random()).collect::<Vec<f32>>();
+        reseed_stream(43);
+        let c = (0..8).map(|_| random()).collect::<Vec<f32>>();
+        assert!(a == b);
+        assert!(a != c);
+    }
+
+    // which grid cells the first pass of a pixel's samples lands in
+    fn strata_hit(samples: u32, pixel_aspect: f32, columns: usize, rows: usize) -> Vec<u32> {
+        let mut hits = vec![0; columns * rows];
+        for index in 0..samples {
+            let kind = SamplerKind::Stratified { samples: samples, pixel_aspect: pixel_aspect };
+            let (u, v) = Samplers::new(kind, index, 5).next_2d();
+            assert!((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v));
+            hits[(v * rows as f32) as usize * columns + (u * columns as f32) as usize] += 1;
+        }
+        hits
+    }
+
+    #[test]
+    fn stratified_square_pixels_use_a_square_grid() {
+        assert!(strata_hit(16, 1.0, 4, 4).iter().all(|&n| n == 1));
+    }
+
+    // a pixel twice as wide as tall gets twice the columns, so each stratum covers a square on screen
+    #[test]
+    fn stratified_wide_pixels_use_a_wide_grid() {
+        assert!(strata_hit(32, 2.0, 8, 4).iter().all(|&n| n == 1));
+        assert!(strata_hit(32, 0.5, 4, 8).iter().all(|&n| n == 1));
+    }
+}
@@ -0,0 +1,156 @@
+use crate::vector::*;
+
+pub const LAMBDA_MIN: f32 = 380.0;
+pub const LAMBDA_MAX: f32 = 780.0;
+
+// integral of the fitted y color matching function over the visible range
+const CIE_Y_INTEGRAL: f32 = 106.91975;
+
+fn gaussian(x: f32, mu: f32, sigma1: f32, sigma2: f32) -> f32 {
+    let t = (x - mu) / if x < mu { sigma1 } else { sigma2 };
+    (-0.5 * t * t).exp()
+}
+
+// multi-lobe fit of the CIE 1931 color matching functions (Wyman, Sloan and Shirley 2013)
+pub fn wavelength_to_xyz(nm: f32) -> V3 {
+    V3(
+        1.056 * gaussian(nm, 599.8, 37.9, 31.0) + 0.362 * gaussian(nm, 442.0, 16.0, 26.7) - 0.065 * gaussian(nm, 501.1, 20.4, 26.2),
+        0.821 * gaussian(nm, 568.8, 46.9, 40.5) + 0.286 * gaussian(nm, 530.9, 16.3, 31.1),
+        1.217 * gaussian(nm, 437.0, 11.8, 36.0) + 0.681 * gaussian(nm, 459.0, 26.0, 13.8),
+    )
+}
+
+pub fn xyz_to_rgb(xyz: V3) -> V3 {
+    V3(
+        3.2406 * xyz.x() - 1.5372 * xyz.y() - 0.4986 * xyz.z(),
+        -0.9689 * xyz.x() + 1.8758 * xyz.y() + 0.0415 * xyz.z(),
+        0.0557 * xyz.x() - 0.2040 * xyz.y() + 1.0570 * xyz.z(),
+    )
+}
+
+pub fn wavelength_to_rgb(nm: f32) -> V3 {
+    xyz_to_rgb(wavelength_to_xyz(nm))
+}
+
+// Planck's law for spectral radiance, up to a constant factor that blackbody_to_rgb normalizes away
+fn planck(nm: f32, kelvin: f32) -> f32 {
+    const HC_OVER_K: f32 = 1.438_777e7; // h c / k_B in nm K
+    let l = nm / 1000.0;
+    1.0 / (l.powi(5) * ((HC_OVER_K / (nm * kelvin)).exp() - 1.0))
+}
+
+// linear rgb of a blackbody at the given temperature, scaled to unit luminance; channels outside the srgb
+// gamut (the red end of very low temperatures) are clipped at zero
+pub fn blackbody_to_rgb(kelvin: f32) -> V3 {
+    const STEPS: usize = 80;
+    let step = (LAMBDA_MAX - LAMBDA_MIN) / STEPS as f32;
+    let mut xyz = V3(0.0, 0.0, 0.0);
+    for i in 0..STEPS {
+        let nm = LAMBDA_MIN + (i as f32 + 0.5) * step;
+        xyz = xyz + wavelength_to_xyz(nm).scale(planck(nm, kelvin));
+    }
+
+    // far below the visible range every term underflows to zero, which has no luminance to normalize by
+    if xyz.y() <= 0.0 {
+        return V3(0.0, 0.0, 0.0);
+    }
+
+    xyz_to_rgb(xyz.scale(1.0 / xyz.y())).map(&|x| x.max(0.0))
+}
+
+// the radiance at nm of a spectrum standing in for an rgb color: the channels share the visible range in
+// proportion to the clamped rgb of each wavelength, so the weights sum to 1 everywhere and a gray stays flat
+pub fn rgb_to_radiance(rgb: V3, nm: f32) -> f32 {
+    let weights = wavelength_to_rgb(nm).map(&|x| x.max(0.0));
+    let total = weights.x() + weights.y() + weights.z();
+    if total <= 0.0 {
+        return (rgb.x() + rgb.y() + rgb.z()) / 3.0;
+    }
+
+    rgb.dot(weights) / total
+}
+
+pub struct SpectralImage {
+    width: usize,
+    height: usize,
+    xyz: Vec<V3>,
+    samples: Vec<u32>,
+}
+
+impl SpectralImage {
+    pub fn new(width: usize, height: usize) -> SpectralImage {
+        SpectralImage {
+            width: width,
+            height: height,
+            xyz: vec![V3(0.0, 0.0, 0.0); width * height],
+            samples: vec![0; width * height],
+        }
+    }
+
+    // radiance carried by a single wavelength drawn uniformly from [LAMBDA_MIN, LAMBDA_MAX]
+    pub fn add_sample(&mut self, x: usize, y: usize, nm: f32, radiance: f32) {
+        let index = y * self.width + x;
+        self.xyz[index] = self.xyz[index] + wavelength_to_xyz(nm).scale(radiance);
+        self.samples[index] += 1;
+    }
+
+    pub fn resolve(&self) -> Vec<V3> {
+        let scale = (LAMBDA_MAX - LAMBDA_MIN) / CIE_Y_INTEGRAL;
+
+        (0..self.width * self.height).map(|index| {
+            if self.samples[index] == 0 {
+                V3(0.0, 0.0, 0.0)
+            } else {
+                xyz_to_rgb(self.xyz[index].scale(scale / self.samples[index] as f32))
+            }
+        }).collect()
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn green_wavelength_is_green_dominant() {
+        let rgb = wavelength_to_rgb(550.0);
+        assert!(rgb.y() > rgb.x() && rgb.y() > rgb.z());
+    }
+
+    #[test]
+    fn blue_wavelength_is_blue_dominant() {
+        let rgb = wavelength_to_rgb(450.0);
+        assert!(rgb.z() > rgb.x() && rgb.z() > rgb.y());
+    }
+
+    #[test]
+    fn gray_spreads_flat_over_the_spectrum() {
+        for i in 0..=40 {
+            let nm = LAMBDA_MIN + (LAMBDA_MAX - LAMBDA_MIN) * i as f32 / 40.0;
+            assert!((rgb_to_radiance(V3(0.5, 0.5, 0.5), nm) - 0.5).abs() < 1e-5);
+        }
+        assert!(rgb_to_radiance(V3(1.0, 0.0, 0.0), 650.0) > rgb_to_radiance(V3(1.0, 0.0, 0.0), 450.0));
+    }
+
+    #[test]
+    fn daylight_blackbody_is_near_white() {
+        for &c in blackbody_to_rgb(6500.0).as_array().iter() {
+            assert!((c - 1.0).abs() < 0.1, "{}", c);
+        }
+    }
+
+    #[test]
+    fn cold_blackbody_is_black_not_nan() {
+        for &kelvin in [1.0, 10.0, 100.0].iter() {
+            assert!(blackbody_to_rgb(kelvin).as_array() == [0.0, 0.0, 0.0]);
+        }
+    }
+}
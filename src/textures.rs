@@ -1,10 +1,17 @@
+use std::io;
+use std::sync::OnceLock;
+use rand::Rng;
+use rand::prng::XorShiftRng;
 use crate::vector::*;
+use crate::sampler::seeded_rng;
+use crate::image::*;
+use crate::color::*;
 
 pub trait Rendering {
     fn value(&self, u: f32, v: f32, point: &V3) -> V3;
 }
 
-struct SolidTexture {
+pub struct SolidTexture {
     color: V3,
 }
 
@@ -22,24 +29,30 @@ impl Rendering for SolidTexture {
     }
 }
 
-struct CheckerTexture {
+pub struct CheckerTexture {
     odd: Box<Textures>,
     even: Box<Textures>,
+    // squares of this size in (u, v); without it the pattern is a 3d one in world space
+    size: Option<f32>,
 }
 
 impl CheckerTexture {
-    fn new(odd: Textures, even: Textures) -> CheckerTexture {
+    fn new(odd: Textures, even: Textures, size: Option<f32>) -> CheckerTexture {
         CheckerTexture {
             odd: Box::new(odd),
             even: Box::new(even),
+            size: size,
         }
     }
 }
 
 impl Rendering for CheckerTexture {
     fn value(&self, u: f32, v: f32, point: &V3) -> V3 {
-        let sines = (10.0 * point.x()).sin() * (10.0 * point.y()).sin() * (10.0 * point.z()).sin();
-        if sines < 0.0 {
+        let odd = match self.size {
+            Some(size) => ((u / size).floor() + (v / size).floor()) as i64 % 2 != 0,
+            None => (10.0 * point.x()).sin() * (10.0 * point.y()).sin() * (10.0 * point.z()).sin() < 0.0,
+        };
+        if odd {
             self.odd.value(u, v, point)
         } else {
             self.even.value(u, v, point)
@@ -54,38 +67,48 @@ struct Perlin {
     perm_z: Vec<u8>,
 }
 
+static GLOBAL_PERLIN: OnceLock<Perlin> = OnceLock::new();
+
+// the tables behind every noise texture are fixed, so a render is the same from run to run
+const PERLIN_SEED: u64 = 0x5eed_9e71;
+
 impl Perlin {
-    fn new() -> Perlin {
+    fn global() -> &'static Perlin {
+        GLOBAL_PERLIN.get_or_init(|| Perlin::with_seed(PERLIN_SEED))
+    }
+
+    fn with_seed(seed: u64) -> Perlin {
+        let mut rng = seeded_rng(seed);
         Perlin {
-            ranvec: Perlin::perlin_generate(),
-            perm_x: Perlin::perlin_generate_perm(),
-            perm_y: Perlin::perlin_generate_perm(),
-            perm_z: Perlin::perlin_generate_perm(),
+            ranvec: Perlin::perlin_generate(&mut rng),
+            perm_x: Perlin::perlin_generate_perm(&mut rng),
+            perm_y: Perlin::perlin_generate_perm(&mut rng),
+            perm_z: Perlin::perlin_generate_perm(&mut rng),
         }
     }
 
-    fn perlin_generate() -> Vec<V3> {
+    fn perlin_generate(rng: &mut XorShiftRng) -> Vec<V3> {
         (0..256).map(|_|
             V3(
-                -1.0 + 2.0 * rand::random::<f32>(),
-                -1.0 + 2.0 * rand::random::<f32>(),
-                -1.0 + 2.0 * rand::random::<f32>(),
+                -1.0 + 2.0 * rng.gen::<f32>(),
+                -1.0 + 2.0 * rng.gen::<f32>(),
+                -1.0 + 2.0 * rng.gen::<f32>(),
             ).normalize()
         ).collect()
     }
 
-    fn permute(vec: &mut Vec<u8>, n: usize) {
+    fn permute(rng: &mut XorShiftRng, vec: &mut Vec<u8>, n: usize) {
         for i in (1..n).rev() {
-            let target = (rand::random::<f32>() * (i + 1) as f32).floor() as usize;
+            let target = (rng.gen::<f32>() * (i + 1) as f32).floor() as usize;
             let (x,y) = (vec[target],vec[i]);
             vec[target] = y;
             vec[i] = x;
         }
     }
 
-    fn perlin_generate_perm() -> Vec<u8> {
+    fn perlin_generate_perm(rng: &mut XorShiftRng) -> Vec<u8> {
         let mut vec = (0..=255).collect();
-        Perlin::permute(&mut vec, 256);
+        Perlin::permute(rng, &mut vec, 256);
         vec
     }
 
@@ -144,15 +167,15 @@ impl Perlin {
     }
 }
 
-struct NoiseTexture {
-    noise: Perlin,
+pub struct NoiseTexture {
+    noise: &'static Perlin,
     scaler: f32,
 }
 
 impl NoiseTexture {
     fn new(scaler: f32) -> NoiseTexture {
         NoiseTexture {
-            noise: Perlin::new(),
+            noise: Perlin::global(),
             scaler: scaler,
         }
     }
@@ -164,10 +187,69 @@ impl Rendering for NoiseTexture {
     }
 }
 
+// turbulence at the hit point picks a color from a ramp of (position, color) stops, for organic variation such as
+// rust or stone without an image
+pub struct NoiseRampTexture {
+    noise: &'static Perlin,
+    // sorted by position
+    ramp: Vec<(f32, V3)>,
+    noise_scale: f32,
+    octaves: i32,
+}
+
+impl NoiseRampTexture {
+    fn new(mut ramp: Vec<(f32, V3)>, noise_scale: f32, octaves: i32) -> NoiseRampTexture {
+        assert!(!ramp.is_empty(), "a noise ramp needs at least one stop");
+        ramp.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        NoiseRampTexture {
+            noise: Perlin::global(),
+            ramp: ramp,
+            noise_scale: noise_scale,
+            octaves: octaves,
+        }
+    }
+
+    // linear between the neighbouring stops, constant beyond the first and the last
+    fn lookup(&self, t: f32) -> V3 {
+        let next = self.ramp.iter().position(|stop| t < stop.0).unwrap_or(self.ramp.len());
+        if next == 0 {
+            return self.ramp[0].1;
+        }
+        if next == self.ramp.len() {
+            return self.ramp[next - 1].1;
+        }
+
+        let ((t0, c0), (t1, c1)) = (self.ramp[next - 1], self.ramp[next]);
+        let s = (t - t0) / (t1 - t0);
+        c0.scale(1.0 - s) + c1.scale(s)
+    }
+}
+
+impl Rendering for NoiseRampTexture {
+    fn value(&self, _u: f32, _v: f32, point: &V3) -> V3 {
+        let t = self.noise.turbulence(&point.scale(self.noise_scale), self.octaves);
+        self.lookup(t.min(1.0))
+    }
+}
+
+pub struct ImageTexture {
+    image: Image,
+}
+
+impl Rendering for ImageTexture {
+    // v runs bottom to top while image rows run top to bottom
+    fn value(&self, u: f32, v: f32, _point: &V3) -> V3 {
+        self.image.sample(u, 1.0 - v)
+    }
+}
+
 pub enum Textures {
     Solid(SolidTexture),
     Checker(CheckerTexture),
     Noise(NoiseTexture),
+    NoiseRamp(NoiseRampTexture),
+    Image(ImageTexture),
 }
 
 impl Textures {
@@ -175,13 +257,34 @@ impl Textures {
         Textures::Solid(SolidTexture::new(color))
     }
 
+    // a color authored in another space, e.g. an ACEScg value picked in a compositing package
+    pub fn solid_in(color: V3, space: ColorSpace) -> Textures {
+        Textures::solid(space.to_linear_srgb(color))
+    }
+
     pub fn checker(even: Textures, odd: Textures) -> Textures {
-        Textures::Checker(CheckerTexture::new(odd, even))
+        Textures::Checker(CheckerTexture::new(odd, even, None))
+    }
+
+    // squares of side size in texture space, e.g. world units on a plane
+    pub fn uv_checker(even: Textures, odd: Textures, size: f32) -> Textures {
+        Textures::Checker(CheckerTexture::new(odd, even, Some(size)))
     }
 
     pub fn noise(scaler: f32) -> Textures {
         Textures::Noise(NoiseTexture::new(scaler))
     }
+
+    // stops are (position, color) with positions in [0,1], in any order; octaves sets how fine the detail gets
+    pub fn noise_ramp(stops: Vec<(f32, V3)>, scale: f32, octaves: i32) -> Textures {
+        Textures::NoiseRamp(NoiseRampTexture::new(stops, scale, octaves))
+    }
+
+    pub fn image(file_name: &str) -> io::Result<Textures> {
+        Ok(Textures::Image(ImageTexture {
+            image: Image::load(file_name)?,
+        }))
+    }
 }
 
 impl Rendering for Textures {
@@ -190,7 +293,25 @@ impl Rendering for Textures {
             Textures::Solid(t) => t.value(u, v, point),
             Textures::Checker(t) => t.value(u, v, point),
             Textures::Noise(t) => t.value(u, v, point),
+            Textures::NoiseRamp(t) => t.value(u, v, point),
+            Textures::Image(t) => t.value(u, v, point),
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(perlin: &Perlin) -> Vec<f32> {
+        [V3(0.3, 1.7, 2.2), V3(3.1, 0.05, 2.9), V3(1.5, 2.25, 0.6)].iter()
+            .map(|point| perlin.turbulence(point, 7))
+            .collect()
+    }
+
+    #[test]
+    fn noise_repeats_for_a_seed() {
+        assert_eq!(samples(&Perlin::with_seed(1)), samples(&Perlin::with_seed(1)));
+        assert_ne!(samples(&Perlin::with_seed(1)), samples(&Perlin::with_seed(2)));
+    }
+}
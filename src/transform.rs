@@ -0,0 +1,173 @@
+use crate::vector::*;
+
+#[derive(Clone, Copy)]
+pub struct Matrix3 {
+    rows: [V3; 3],
+}
+
+impl Matrix3 {
+    pub fn new(r0: V3, r1: V3, r2: V3) -> Matrix3 {
+        Matrix3 {
+            rows: [r0, r1, r2],
+        }
+    }
+
+    pub fn identity() -> Matrix3 {
+        Matrix3::diagonal(V3(1.0, 1.0, 1.0))
+    }
+
+    pub fn diagonal(d: V3) -> Matrix3 {
+        Matrix3::new(V3(d.0, 0.0, 0.0), V3(0.0, d.1, 0.0), V3(0.0, 0.0, d.2))
+    }
+
+    pub fn apply(&self, v: V3) -> V3 {
+        V3(self.rows[0].dot(v), self.rows[1].dot(v), self.rows[2].dot(v))
+    }
+
+    pub fn transpose(&self) -> Matrix3 {
+        let r = &self.rows;
+        Matrix3::new(
+            V3(r[0].0, r[1].0, r[2].0),
+            V3(r[0].1, r[1].1, r[2].1),
+            V3(r[0].2, r[1].2, r[2].2),
+        )
+    }
+
+    pub fn mul(&self, other: &Matrix3) -> Matrix3 {
+        let t = other.transpose();
+        Matrix3::new(
+            V3(self.rows[0].dot(t.rows[0]), self.rows[0].dot(t.rows[1]), self.rows[0].dot(t.rows[2])),
+            V3(self.rows[1].dot(t.rows[0]), self.rows[1].dot(t.rows[1]), self.rows[1].dot(t.rows[2])),
+            V3(self.rows[2].dot(t.rows[0]), self.rows[2].dot(t.rows[1]), self.rows[2].dot(t.rows[2])),
+        )
+    }
+
+    pub fn determinant(&self) -> f32 {
+        self.rows[0].dot(self.rows[1].cross(self.rows[2]))
+    }
+
+    pub fn inverse(&self) -> Option<Matrix3> {
+        let det = self.determinant();
+        if det.abs() < 1e-12 {
+            return None;
+        }
+
+        let r = &self.rows;
+        let cofactors = Matrix3::new(r[1].cross(r[2]), r[2].cross(r[0]), r[0].cross(r[1]));
+        let t = cofactors.transpose();
+        Some(Matrix3::new(t.rows[0].scale(1.0 / det), t.rows[1].scale(1.0 / det), t.rows[2].scale(1.0 / det)))
+    }
+}
+
+// affine map p -> linear * p + offset, with the inverse kept alongside for ray transformation
+#[derive(Clone, Copy)]
+pub struct Transform {
+    linear: Matrix3,
+    inverse: Matrix3,
+    offset: V3,
+}
+
+impl Transform {
+    pub fn new(linear: Matrix3, offset: V3) -> Transform {
+        Transform {
+            linear: linear,
+            inverse: linear.inverse().expect("transform must be invertible"),
+            offset: offset,
+        }
+    }
+
+    pub fn identity() -> Transform {
+        Transform::new(Matrix3::identity(), V3(0.0, 0.0, 0.0))
+    }
+
+    pub fn translate(offset: V3) -> Transform {
+        Transform::new(Matrix3::identity(), offset)
+    }
+
+    pub fn scale(factors: V3) -> Transform {
+        Transform::new(Matrix3::diagonal(factors), V3(0.0, 0.0, 0.0))
+    }
+
+    pub fn rotate_y(angle: f32) -> Transform {
+        let radians = (std::f32::consts::PI / 180.0) * angle;
+        let (sin_theta, cos_theta) = (radians.sin(), radians.cos());
+
+        Transform::new(Matrix3::new(
+            V3(cos_theta, 0.0, sin_theta),
+            V3(0.0, 1.0, 0.0),
+            V3(-sin_theta, 0.0, cos_theta),
+        ), V3(0.0, 0.0, 0.0))
+    }
+
+    // applies self first, then other
+    pub fn then(&self, other: &Transform) -> Transform {
+        Transform::new(other.linear.mul(&self.linear), other.linear.apply(self.offset) + other.offset)
+    }
+
+    pub fn determinant(&self) -> f32 {
+        self.linear.determinant()
+    }
+
+    // the per-axis factors when the linear part is a scale along the axes with no flip, which maps axis-aligned
+    // geometry to axis-aligned geometry
+    pub fn axis_scale(&self) -> Option<V3> {
+        let r = &self.linear.rows;
+        let off_diagonal = [r[0].1, r[0].2, r[1].0, r[1].2, r[2].0, r[2].1];
+        if off_diagonal.iter().all(|&c| c == 0.0) && r[0].0 > 0.0 && r[1].1 > 0.0 && r[2].2 > 0.0 {
+            Some(V3(r[0].0, r[1].1, r[2].2))
+        } else {
+            None
+        }
+    }
+
+    pub fn point(&self, p: V3) -> V3 {
+        self.linear.apply(p) + self.offset
+    }
+
+    pub fn vector(&self, v: V3) -> V3 {
+        self.linear.apply(v)
+    }
+
+    // normals go through the inverse transpose so they stay perpendicular under non-uniform scale
+    pub fn normal(&self, n: V3) -> V3 {
+        self.inverse.transpose().apply(n).normalize()
+    }
+
+    pub fn inverse_point(&self, p: V3) -> V3 {
+        self.inverse.apply(p - self.offset)
+    }
+
+    pub fn inverse_vector(&self, v: V3) -> V3 {
+        self.inverse.apply(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::figures::*;
+
+    #[test]
+    fn normals_stay_perpendicular_under_non_uniform_scale() {
+        // a unit sphere stretched to the ellipsoid x^2/4 + y^2 + z^2 = 1, whose normal follows the gradient
+        // (x/4, y, z) rather than the stretched sphere normal
+        let ellipsoid = Figures::scale(V3(2.0, 1.0, 1.0), Figures::sphere(V3(0.0, 0.0, 0.0), 1.0));
+        let origin = V3(4.0, 3.0, 0.0);
+        let ray = Ray { origin: origin, direction: V3U::new(V3(0.0, 0.0, 0.0) - origin), time: 0.0, wavelength: None };
+        let rec = ellipsoid.hit(&ray, 0.001, std::f32::MAX).unwrap();
+
+        let p = rec.point;
+        let gradient = V3(p.x() / 4.0, p.y(), p.z()).normalize();
+        assert!((rec.normal.dot(gradient) - 1.0).abs() < 1e-4);
+        assert!((p.x() * p.x() / 4.0 + p.y() * p.y() + p.z() * p.z() - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn normal_is_inverse_transpose() {
+        let scale = Transform::scale(V3(2.0, 1.0, 1.0));
+        let normal = scale.normal(V3(1.0, 1.0, 0.0).normalize());
+        let tangent = scale.vector(V3(-1.0, 1.0, 0.0));
+        assert!(normal.dot(tangent).abs() < 1e-6);
+        assert!((normal.norm() - 1.0).abs() < 1e-6);
+    }
+}
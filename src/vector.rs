@@ -1,5 +1,7 @@
 use std::ops::*;
 use std::iter::Sum;
+use rand::Rng;
+use crate::sampler::*;
 
 pub trait Dim3 {
     fn x(&self) -> f32;
@@ -20,17 +22,26 @@ pub struct V3(pub f32, pub f32, pub f32);
 
 impl V3 {
     pub fn new_in_unit_sphere() -> V3 {
+        with_stream(V3::in_unit_sphere_from)
+    }
+
+    pub fn new_in_unit_disk() -> V3 {
+        with_stream(V3::in_unit_disk_from)
+    }
+
+    // same rejection sampling as above, but driven by the given generator so that results are reproducible
+    pub fn in_unit_sphere_from(rng: &mut impl Rng) -> V3 {
         loop {
-            let p = V3(rand::random::<f32>(), rand::random::<f32>(), rand::random::<f32>()).scale(2.0) - V3(1.0, 1.0, 1.0);
+            let p = V3(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>()).scale(2.0) - V3(1.0, 1.0, 1.0);
             if p.square_norm() < 1.0 {
                 return p;
             }
         }
     }
 
-    pub fn new_in_unit_disk() -> V3 {
+    pub fn in_unit_disk_from(rng: &mut impl Rng) -> V3 {
         loop {
-            let p = V3(rand::random::<f32>(), rand::random::<f32>(), 0.0).scale(2.0) - V3(1.0, 1.0, 0.0);
+            let p = V3(rng.gen::<f32>(), rng.gen::<f32>(), 0.0).scale(2.0) - V3(1.0, 1.0, 0.0);
             if p.square_norm() < 1.0 {
                 return p;
             }
@@ -64,6 +75,34 @@ impl V3 {
     pub fn map(self, f: &Fn(f32) -> f32) -> V3 {
         V3(f(self.0), f(self.1), f(self.2))
     }
+
+    pub fn as_array(&self) -> [f32; 3] {
+        [self.0, self.1, self.2]
+    }
+}
+
+impl From<[f32; 3]> for V3 {
+    fn from(a: [f32; 3]) -> V3 {
+        V3(a[0], a[1], a[2])
+    }
+}
+
+impl From<(f32, f32, f32)> for V3 {
+    fn from(t: (f32, f32, f32)) -> V3 {
+        V3(t.0, t.1, t.2)
+    }
+}
+
+impl From<V3> for [f32; 3] {
+    fn from(v: V3) -> [f32; 3] {
+        v.as_array()
+    }
+}
+
+impl From<V3> for (f32, f32, f32) {
+    fn from(v: V3) -> (f32, f32, f32) {
+        (v.0, v.1, v.2)
+    }
 }
 
 impl Dim3 for V3 {
@@ -133,6 +172,13 @@ impl V3U {
         V3U(v.normalize())
     }
 
+    // for vectors that are unit length by construction (a rotated unit vector, an axis), skipping the sqrt of
+    // new; the length is only checked in debug builds
+    pub fn from_normalized_unchecked(v: V3) -> V3U {
+        debug_assert!((v.square_norm() - 1.0).abs() < 1e-3, "expected a unit vector, got square norm {}", v.square_norm());
+        V3U(v)
+    }
+
     pub fn as_V3(self) -> V3 {
         self.0
     }
@@ -164,6 +210,10 @@ impl Dim3Dot<V3U> for V3 {}
 pub struct Ray {
     pub origin: V3,
     pub direction: V3U,
+    // when the ray was sent within the camera's shutter interval; moving figures are hit where they are then
+    pub time: f32,
+    // the wavelength in nm a spectral render traces this ray at, for materials that depend on it; None in RGB
+    pub wavelength: Option<f32>,
 }
 
 impl Ray {
@@ -172,4 +222,53 @@ impl Ray {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions_round_trip() {
+        let v = V3(1.0, -2.5, 3.25);
+        assert!(v.as_array() == [1.0, -2.5, 3.25]);
 
+        let array: [f32; 3] = v.into();
+        assert!(V3::from(array).as_array() == v.as_array());
+
+        let tuple: (f32, f32, f32) = v.into();
+        assert!(tuple == (1.0, -2.5, 3.25));
+        assert!(V3::from(tuple).as_array() == v.as_array());
+    }
+
+    #[test]
+    fn unit_samples_repeat_for_a_seed() {
+        let sphere = |seed| {
+            let mut rng = seeded_rng(seed);
+            (0..16).map(|_| V3::in_unit_sphere_from(&mut rng).as_array()).collect::<Vec<_>>()
+        };
+        let disk = |seed| {
+            let mut rng = seeded_rng(seed);
+            (0..16).map(|_| V3::in_unit_disk_from(&mut rng).as_array()).collect::<Vec<_>>()
+        };
+
+        assert!(sphere(1) == sphere(1) && sphere(1) != sphere(2));
+        assert!(disk(1) == disk(1) && disk(1) != disk(2));
+    }
+
+    #[test]
+    fn unit_samples_are_uniform() {
+        let mut rng = seeded_rng(9);
+        let n = 20000;
+        let sphere = (0..n).map(|_| V3::in_unit_sphere_from(&mut rng)).collect::<Vec<V3>>();
+        let disk = (0..n).map(|_| V3::in_unit_disk_from(&mut rng)).collect::<Vec<V3>>();
+
+        // the inner half radius holds 1/8 of a ball's volume and 1/4 of a disk's area
+        let inner = |points: &[V3]| points.iter().filter(|p| p.norm() < 0.5).count() as f32 / n as f32;
+        assert!((inner(&sphere) - 0.125).abs() < 0.01);
+        assert!((inner(&disk) - 0.25).abs() < 0.015);
+
+        let mean = |points: &[V3]| points.iter().cloned().sum::<V3>().scale(1.0 / n as f32);
+        assert!(mean(&sphere).norm() < 0.02 && mean(&disk).norm() < 0.02);
+        assert!(sphere.iter().all(|p| p.square_norm() < 1.0));
+        assert!(disk.iter().all(|p| p.square_norm() < 1.0 && p.z() == 0.0));
+    }
+}